@@ -29,6 +29,7 @@
           libclang
           libxkbcommon
           linux-pam
+          libxcrypt
           libGL
           wayland
           makeWrapper
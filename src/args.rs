@@ -1,11 +1,21 @@
 pub struct Args {
     pub fallback_config: bool,
+    pub software_rendering: bool,
+    pub ready_fd: Option<std::os::fd::RawFd>,
+    pub daemonize: bool,
+    pub pam_service: Option<String>,
+    pub clock_format: Option<String>,
 }
 
 pub fn parse_args() -> std::result::Result<Args, lexopt::Error> {
     use lexopt::prelude::*;
 
     let mut fallback_config = true;
+    let mut software_rendering = false;
+    let mut ready_fd = None;
+    let mut daemonize = false;
+    let mut pam_service = None;
+    let mut clock_format = None;
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
         match arg {
@@ -15,13 +25,33 @@ pub fn parse_args() -> std::result::Result<Args, lexopt::Error> {
             Long("no-fallback") => {
                 fallback_config = false;
             }
+            Long("software-rendering") => {
+                software_rendering = true;
+            }
+            Long("ready-fd") => {
+                ready_fd = Some(parser.value()?.parse()?);
+            }
+            Long("daemonize") => {
+                daemonize = true;
+            }
+            Long("pam-service") => {
+                pam_service = Some(parser.value()?.string()?);
+            }
+            Long("clock-format") => {
+                clock_format = Some(parser.value()?.string()?);
+            }
             Short('h') | Long("help") => {
                 println!(
                     "Usage: cthulock [OPTIONS]
 
 Options:
 --fallback              show a fallback lockscreen if loading your component fails (default)
---no-fallback           don't show a fallback, use only in testing"
+--no-fallback           don't show a fallback, use only in testing
+--software-rendering    render on the CPU instead of using OpenGL
+--ready-fd <N>          write a newline to file descriptor N once the session is locked
+--daemonize             fork into the background once the session is locked
+--pam-service <NAME>    authenticate with the PAM service NAME instead of cthulock
+--clock-format <FORMAT> strftime format of clock_text, e.g. \"%I:%M %p\""
                 );
                 std::process::exit(0);
             }
@@ -29,5 +59,12 @@ Options:
         }
     }
 
-    Ok(Args { fallback_config })
+    Ok(Args {
+        fallback_config,
+        software_rendering,
+        ready_fd,
+        daemonize,
+        pam_service,
+        clock_format,
+    })
 }
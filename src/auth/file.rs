@@ -0,0 +1,137 @@
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use crate::{
+    auth::{AuthFailure, Authenticator, Conversation},
+    message::UnlockFailure,
+    secret::Secret,
+};
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt(phrase: *const c_char, setting: *const c_char) -> *mut c_char;
+}
+
+// crypt(3) returns a pointer to a static buffer
+static CRYPT: Mutex<()> = Mutex::new(());
+
+/// Checks passwords against crypt(3) hashes in a file of `user:hash` lines. Further fields are ignored, so /etc/shadow lines work too.
+/// Lets kiosks unlock without setting up PAM
+pub struct FileAuthenticator {
+    path: PathBuf,
+}
+
+impl FileAuthenticator {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The hash stored for `user`. The file is read on every attempt, so changes apply while locked
+    fn hash_of(&self, user: &str) -> Result<String, AuthFailure> {
+        let contents = std::fs::read_to_string(&self.path).map_err(|e| {
+            log::error!("failed to read {}: {e}", self.path.display());
+            AuthFailure::new(
+                UnlockFailure::Misconfigured,
+                format!("Cannot read {}: {e}", self.path.display()),
+            )
+        })?;
+        contents
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split(':'))
+            .find_map(|mut fields| {
+                // the hash is the second field, shadow lines continue with password aging fields
+                (fields.next() == Some(user)).then(|| fields.next())?
+            })
+            .map(|hash| hash.trim().to_owned())
+            .ok_or_else(|| {
+                AuthFailure::new(UnlockFailure::UnknownUser, format!("Unknown user {user}"))
+            })
+    }
+}
+
+impl Authenticator for FileAuthenticator {
+    fn authenticate(
+        &mut self,
+        user: &str,
+        password: Secret,
+        _: &mut dyn Conversation,
+    ) -> Result<(), AuthFailure> {
+        let hash = self.hash_of(user)?;
+        let unsupported = || {
+            AuthFailure::new(
+                UnlockFailure::Misconfigured,
+                format!("The password hash of {user} is not supported"),
+            )
+        };
+        let (Some(phrase), Ok(setting)) = (password.as_c_str(), CString::new(hash.as_str())) else {
+            return Err(AuthFailure::new(
+                UnlockFailure::WrongPassword,
+                "Wrong password",
+            ));
+        };
+
+        let _guard = CRYPT.lock().unwrap();
+        let result = unsafe { crypt(phrase.as_ptr(), setting.as_ptr()) };
+        if result.is_null() {
+            return Err(unsupported());
+        }
+        let result = unsafe { CStr::from_ptr(result) }.to_bytes();
+        // failures are reported as a string starting with '*', which never matches a valid hash
+        if result.starts_with(b"*") {
+            return Err(unsupported());
+        }
+
+        // compare in constant time, the hash must not leak through timing
+        let matches = result.len() == hash.len()
+            && result
+                .iter()
+                .zip(hash.as_bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+        if matches {
+            Ok(())
+        } else {
+            Err(AuthFailure::new(
+                UnlockFailure::WrongPassword,
+                "Wrong password",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAuthenticator;
+    use crate::{
+        auth::{mock::NoConversation, Authenticator},
+        message::UnlockFailure,
+    };
+
+    #[test]
+    fn test_password_file() {
+        let path = std::env::temp_dir().join(format!("cthulock-passwords-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# kiosk users\n\
+             kiosk:$6$cthulock$cBXaFDWvBtL0tVaieZTk8BMxcOWYBCarHgNRlLnt8s7VnQHFxHRmPZJSrvbjAlKpXRAb4ZABUabVFD4uxMeVH/\n\
+             shadow:$6$cthulock$cBXaFDWvBtL0tVaieZTk8BMxcOWYBCarHgNRlLnt8s7VnQHFxHRmPZJSrvbjAlKpXRAb4ZABUabVFD4uxMeVH/:19000:0:99999:7:::\n",
+        )
+        .unwrap();
+        let mut authenticator = FileAuthenticator::new(path.clone());
+
+        let result = authenticator.authenticate("kiosk", "hunter2".into(), &mut NoConversation);
+        assert_eq!(result, Ok(()));
+        let result = authenticator.authenticate("shadow", "hunter2".into(), &mut NoConversation);
+        assert_eq!(result, Ok(()));
+        let result = authenticator.authenticate("kiosk", "hunter3".into(), &mut NoConversation);
+        assert_eq!(result.unwrap_err().reason, UnlockFailure::WrongPassword);
+        let result = authenticator.authenticate("admin", "hunter2".into(), &mut NoConversation);
+        assert_eq!(result.unwrap_err().reason, UnlockFailure::UnknownUser);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    auth::{AuthFailure, Authenticator, Conversation},
+    message::UnlockFailure,
+    secret::Secret,
+};
+
+/// Accepts a single user with a fixed password, optionally followed by a one-time password
+pub struct MockAuthenticator {
+    user: String,
+    password: String,
+    one_time_password: Option<String>,
+}
+
+impl MockAuthenticator {
+    pub fn new(user: &str, password: &str) -> Self {
+        Self {
+            user: user.to_owned(),
+            password: password.to_owned(),
+            one_time_password: None,
+        }
+    }
+
+    /// Ask for `one_time_password` after the password was accepted
+    pub fn with_one_time_password(mut self, one_time_password: &str) -> Self {
+        self.one_time_password = Some(one_time_password.to_owned());
+        self
+    }
+}
+
+impl Authenticator for MockAuthenticator {
+    fn authenticate(
+        &mut self,
+        user: &str,
+        password: Secret,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthFailure> {
+        if user != self.user {
+            return Err(AuthFailure::new(UnlockFailure::UnknownUser, "Unknown user"));
+        }
+        if !matches(&password, &self.password) {
+            return Err(AuthFailure::new(
+                UnlockFailure::WrongPassword,
+                "Wrong password",
+            ));
+        }
+        if let Some(expected) = &self.one_time_password {
+            let answer = conversation.prompt("One-time password:", false);
+            if !answer.is_some_and(|answer| matches(&answer, expected)) {
+                return Err(AuthFailure::new(
+                    UnlockFailure::WrongPassword,
+                    "Wrong one-time password",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches(secret: &Secret, expected: &str) -> bool {
+    secret
+        .as_c_str()
+        .is_some_and(|secret| secret.to_bytes() == expected.as_bytes())
+}
+
+/// A conversation without anyone to answer prompts
+pub struct NoConversation;
+
+impl Conversation for NoConversation {
+    fn prompt(&mut self, _: &str, _: bool) -> Option<Secret> {
+        None
+    }
+
+    fn message(&mut self, _: &str) {}
+}
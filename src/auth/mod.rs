@@ -0,0 +1,407 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    common::CthulockError,
+    config::{AuthBackend, AuthConfig},
+    message::{AuthRequest, UnlockFailure, WindowingMessage},
+    secret::Secret,
+    Result,
+};
+
+mod file;
+#[cfg(test)]
+mod mock;
+mod pam;
+
+pub use file::FileAuthenticator;
+pub use pam::{parallel_auth_thread, PamAuthenticator};
+
+/// Checks the password of a user
+pub trait Authenticator: Send {
+    /// Returns why the session may not be unlocked. Further input can be asked for through `conversation`
+    fn authenticate(
+        &mut self,
+        user: &str,
+        password: Secret,
+        conversation: &mut dyn Conversation,
+    ) -> std::result::Result<(), AuthFailure>;
+}
+
+/// Lets an authenticator talk to the user while checking a password
+pub trait Conversation {
+    /// Ask for more input, e.g. a one-time password. Returns `None` if no answer can be given
+    fn prompt(&mut self, prompt: &str, secret: bool) -> Option<Secret>;
+    /// Show an informational or error message
+    fn message(&mut self, message: &str);
+}
+
+/// Why an attempt failed, as shown to the user
+#[derive(Debug, PartialEq)]
+pub struct AuthFailure {
+    pub reason: UnlockFailure,
+    pub message: String,
+}
+
+impl AuthFailure {
+    pub fn new(reason: UnlockFailure, message: impl Into<String>) -> Self {
+        Self {
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+/// The authenticator selected by `backend` in the `[auth]` table
+pub fn authenticator(config: &AuthConfig) -> Result<Box<dyn Authenticator>> {
+    Ok(match config.backend {
+        AuthBackend::Pam => {
+            // unix_chkpwd only checks the password of the user calling it unless that is root
+            if !config.allowed_users.is_empty() && unsafe { libc::geteuid() } != 0 {
+                log::warn!(
+                    "allowed_users only works with PAM modules that can check the passwords of other users, \
+                     pam_unix rejects them as wrong passwords"
+                );
+            }
+            Box::new(PamAuthenticator::new(config.service.clone()))
+        }
+        AuthBackend::File => {
+            let path: PathBuf = config.password_file.clone().ok_or_else(|| {
+                CthulockError::Generic("backend = \"file\" needs a password_file".to_owned())
+            })?;
+            Box::new(FileAuthenticator::new(path))
+        }
+    })
+}
+
+/// Checks every password submitted by the UI thread, one at a time.
+/// `on_success` is called when the session may be unlocked, `on_failure` with the failed attempts so far otherwise
+pub fn auth_thread(
+    requests: Receiver<AuthRequest>,
+    ui: Sender<WindowingMessage>,
+    config: AuthConfig,
+    mut authenticator: Box<dyn Authenticator>,
+    mut on_success: impl FnMut(),
+    mut on_failure: impl FnMut(&AuthFailure, u32),
+) {
+    let mut throttle = Throttle::new(&config);
+    let session_user = whoami::username();
+
+    while let Ok(AuthRequest::Submit { password, username }) = requests.recv() {
+        let retry_after = throttle.retry_after(Instant::now());
+        let user = username.unwrap_or_else(|| session_user.clone());
+        let failure = if !retry_after.is_zero() {
+            log::info!("rejecting attempt, next one allowed in {retry_after:?}");
+            AuthFailure::new(
+                UnlockFailure::Throttled,
+                "Too many failed attempts, try again later",
+            )
+        } else if user != session_user && !config.allowed_users.contains(&user) {
+            log::warn!("user '{user}' is not allowed to unlock the session of '{session_user}'");
+            throttle.record_failure(Instant::now());
+            AuthFailure::new(
+                UnlockFailure::NotAllowed,
+                format!("{user} is not allowed to unlock this session"),
+            )
+        } else {
+            let mut conversation = UiConversation {
+                ui: ui.clone(),
+                answers: &requests,
+            };
+            match authenticator.authenticate(&user, password, &mut conversation) {
+                Ok(()) => {
+                    on_success();
+                    continue;
+                }
+                Err(failure) => {
+                    throttle.record_failure(Instant::now());
+                    failure
+                }
+            }
+        };
+        reject_queued(&requests);
+        on_failure(&failure, throttle.failed_attempts);
+
+        let _ = ui.send(WindowingMessage::UnlockFailed {
+            reason: failure.reason,
+            message: failure.message,
+            failed_attempts: throttle.failed_attempts,
+            retry_after: throttle.retry_after(Instant::now()),
+        });
+    }
+}
+
+/// Delays further attempts with exponential backoff once too many have failed
+struct Throttle {
+    failed_attempts: u32,
+    throttle_after: u32,
+    delay: Duration,
+    max_delay: Duration,
+    blocked_until: Option<Instant>,
+}
+
+impl Throttle {
+    fn new(config: &AuthConfig) -> Self {
+        Self {
+            failed_attempts: 0,
+            throttle_after: config.throttle_after,
+            delay: Duration::from_secs(config.throttle_seconds),
+            max_delay: Duration::from_secs(config.max_throttle_seconds),
+            blocked_until: None,
+        }
+    }
+
+    /// Time until the next attempt is allowed
+    fn retry_after(&self, now: Instant) -> Duration {
+        self.blocked_until
+            .map_or(Duration::ZERO, |until| until.saturating_duration_since(now))
+    }
+
+    fn record_failure(&mut self, now: Instant) {
+        self.failed_attempts += 1;
+        // the attempt after the `throttle_after`th failure waits `delay`, every further one twice as long.
+        // nothing can be delayed before the first failure
+        let Some(throttled) = self.failed_attempts.checked_sub(self.throttle_after.max(1)) else {
+            return;
+        };
+        let delay = self
+            .delay
+            .saturating_mul(1 << throttled.min(16))
+            .min(self.max_delay);
+        if !delay.is_zero() {
+            self.blocked_until = Some(now + delay);
+        }
+    }
+}
+
+/// Drop submits that don't answer a prompt, they were sent while a password was being checked
+fn reject_queued(requests: &Receiver<AuthRequest>) {
+    while requests.try_recv().is_ok() {
+        log::info!("ignoring submit while a password is being checked");
+    }
+}
+
+/// Forwards prompts and messages to the UI thread and waits for the answers typed there
+struct UiConversation<'a> {
+    ui: Sender<WindowingMessage>,
+    answers: &'a Receiver<AuthRequest>,
+}
+
+impl Conversation for UiConversation<'_> {
+    fn prompt(&mut self, prompt: &str, secret: bool) -> Option<Secret> {
+        reject_queued(self.answers);
+        self.ui
+            .send(WindowingMessage::AuthPrompt {
+                prompt: prompt.to_owned(),
+                secret,
+            })
+            .ok()?;
+        let AuthRequest::Submit { password, .. } = self.answers.recv().ok()?;
+        Some(password)
+    }
+
+    fn message(&mut self, message: &str) {
+        let _ = self.ui.send(WindowingMessage::AuthMessage {
+            message: message.to_owned(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::{self, Receiver, Sender},
+        thread::JoinHandle,
+        time::{Duration, Instant},
+    };
+
+    use super::{auth_thread, mock::MockAuthenticator, Throttle};
+    use crate::{
+        config::AuthConfig,
+        message::{AuthRequest, UnlockFailure, WindowingMessage},
+    };
+
+    struct Harness {
+        requests: Sender<AuthRequest>,
+        ui: Receiver<WindowingMessage>,
+        unlocked: Receiver<()>,
+        thread: JoinHandle<()>,
+    }
+
+    impl Harness {
+        fn new(config: AuthConfig, authenticator: MockAuthenticator) -> Self {
+            let (requests, request_receiver) = mpsc::channel();
+            let (ui_sender, ui) = mpsc::channel();
+            let (unlock_sender, unlocked) = mpsc::channel();
+            let thread = std::thread::spawn(move || {
+                auth_thread(
+                    request_receiver,
+                    ui_sender,
+                    config,
+                    Box::new(authenticator),
+                    || unlock_sender.send(()).unwrap(),
+                    |_, _| {},
+                )
+            });
+            Self {
+                requests,
+                ui,
+                unlocked,
+                thread,
+            }
+        }
+
+        fn submit(&self, password: &str, username: Option<&str>) {
+            self.requests
+                .send(AuthRequest::Submit {
+                    password: password.into(),
+                    username: username.map(ToOwned::to_owned),
+                })
+                .unwrap();
+        }
+
+        fn next_message(&self) -> WindowingMessage {
+            self.ui.recv_timeout(Duration::from_secs(5)).unwrap()
+        }
+
+        /// Stop the thread and return whether the session was unlocked
+        fn finish(self) -> bool {
+            drop(self.requests);
+            self.thread.join().unwrap();
+            self.unlocked.try_recv().is_ok()
+        }
+    }
+
+    fn session_user() -> String {
+        whoami::username()
+    }
+
+    #[test]
+    fn test_throttle_backoff() {
+        let config = AuthConfig {
+            throttle_after: 2,
+            throttle_seconds: 5,
+            max_throttle_seconds: 15,
+            ..Default::default()
+        };
+        let mut throttle = Throttle::new(&config);
+        let now = Instant::now();
+
+        throttle.record_failure(now);
+        assert_eq!(throttle.retry_after(now), Duration::ZERO);
+
+        throttle.record_failure(now);
+        assert_eq!(throttle.retry_after(now), Duration::from_secs(5));
+        assert_eq!(
+            throttle.retry_after(now + Duration::from_secs(2)),
+            Duration::from_secs(3)
+        );
+        throttle.record_failure(now);
+        assert_eq!(throttle.retry_after(now), Duration::from_secs(10));
+        throttle.record_failure(now);
+        assert_eq!(throttle.retry_after(now), Duration::from_secs(15));
+        assert_eq!(throttle.failed_attempts, 4);
+    }
+
+    #[test]
+    fn test_unlock_with_password() {
+        let harness = Harness::new(
+            AuthConfig::default(),
+            MockAuthenticator::new(&session_user(), "hunter2"),
+        );
+        harness.submit("hunter2", None);
+        harness
+            .unlocked
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert!(harness.ui.try_recv().is_err());
+        harness.finish();
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let config = AuthConfig {
+            throttle_after: 2,
+            ..Default::default()
+        };
+        let harness = Harness::new(config, MockAuthenticator::new(&session_user(), "hunter2"));
+
+        harness.submit("*******", None);
+        let WindowingMessage::UnlockFailed {
+            reason,
+            failed_attempts,
+            retry_after,
+            ..
+        } = harness.next_message()
+        else {
+            panic!("expected UnlockFailed");
+        };
+        assert_eq!(reason, UnlockFailure::WrongPassword);
+        assert_eq!(failed_attempts, 1);
+        assert_eq!(retry_after, Duration::ZERO);
+
+        harness.submit("*******", None);
+        let WindowingMessage::UnlockFailed { retry_after, .. } = harness.next_message() else {
+            panic!("expected UnlockFailed");
+        };
+        assert!(!retry_after.is_zero());
+
+        // even the right password is rejected until the delay passed
+        harness.submit("hunter2", None);
+        let WindowingMessage::UnlockFailed {
+            reason,
+            failed_attempts,
+            ..
+        } = harness.next_message()
+        else {
+            panic!("expected UnlockFailed");
+        };
+        assert_eq!(reason, UnlockFailure::Throttled);
+        assert_eq!(failed_attempts, 2);
+        assert!(!harness.finish());
+    }
+
+    #[test]
+    fn test_follow_up_prompt() {
+        let harness = Harness::new(
+            AuthConfig::default(),
+            MockAuthenticator::new(&session_user(), "hunter2").with_one_time_password("123456"),
+        );
+        harness.submit("hunter2", None);
+        let WindowingMessage::AuthPrompt { secret, .. } = harness.next_message() else {
+            panic!("expected AuthPrompt");
+        };
+        assert!(!secret);
+        harness.submit("123456", None);
+        harness
+            .unlocked
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        harness.finish();
+    }
+
+    #[test]
+    fn test_allowed_users() {
+        let config = AuthConfig {
+            allowed_users: vec!["admin".to_owned()],
+            ..Default::default()
+        };
+        let harness = Harness::new(config, MockAuthenticator::new("admin", "root"));
+
+        harness.submit("root", Some("intruder"));
+        let WindowingMessage::UnlockFailed { reason, .. } = harness.next_message() else {
+            panic!("expected UnlockFailed");
+        };
+        assert_eq!(reason, UnlockFailure::NotAllowed);
+
+        harness.submit("root", Some("admin"));
+        harness
+            .unlocked
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        harness.finish();
+    }
+}
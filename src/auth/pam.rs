@@ -0,0 +1,341 @@
+use pam_client::{Context, ConversationHandler, ErrorCode, Flag};
+use std::{
+    ffi::{CStr, CString},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    auth::{AuthFailure, Authenticator, Conversation},
+    message::{UnlockFailure, WindowingMessage},
+    secret::Secret,
+};
+
+/// Checks passwords with a PAM service in /etc/pam.d
+pub struct PamAuthenticator {
+    service: String,
+}
+
+impl PamAuthenticator {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+impl Authenticator for PamAuthenticator {
+    fn authenticate(
+        &mut self,
+        user: &str,
+        password: Secret,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthFailure> {
+        let service = self.service.as_str();
+        let conversation = PamConversation {
+            conversation,
+            first_answer: Some(password),
+        };
+        let mut context = match Context::new(service, Some(user), conversation) {
+            Ok(context) => context,
+            Err(e) => {
+                log::error!("failed to initialize PAM context: {e}");
+                return Err(misconfigured(service, e));
+            }
+        };
+
+        if let Err(e) = context.authenticate(Flag::NONE) {
+            log::info!("authentication failed: {e}");
+            return Err(pam_failure(service, user, e));
+        }
+        match context.acct_mgmt(Flag::NONE) {
+            Ok(()) => Ok(()),
+            // the password expired, PAM asks for a new one through the conversation
+            Err(e) if e.code() == ErrorCode::NEW_AUTHTOK_REQD => context
+                .chauthtok(Flag::CHANGE_EXPIRED_AUTHTOK)
+                .map_err(|e| {
+                    log::info!("changing the expired password failed: {e}");
+                    AuthFailure::new(
+                        UnlockFailure::PasswordChangeFailed,
+                        format!("Changing the expired password failed: {e}"),
+                    )
+                }),
+            Err(e) => {
+                log::info!("account is not valid: {e}");
+                Err(pam_failure(service, user, e))
+            }
+        }
+    }
+}
+
+/// Passes PAM prompts and messages on to a `Conversation`
+struct PamConversation<'a> {
+    conversation: &'a mut dyn Conversation,
+    // the password submitted to start the authentication answers the first prompt
+    first_answer: Option<Secret>,
+}
+
+impl PamConversation<'_> {
+    fn prompt(&mut self, prompt: &CStr, secret: bool) -> Result<CString, ErrorCode> {
+        let answer = match self.first_answer.take() {
+            Some(answer) => answer,
+            None => self
+                .conversation
+                .prompt(&prompt.to_string_lossy(), secret)
+                .ok_or(ErrorCode::CONV_ERR)?,
+        };
+        // pam-client only accepts a `CString`. It copies it for PAM with strdup(3) and frees it without wiping it,
+        // see docs/overview.md
+        answer
+            .as_c_str()
+            .map(CStr::to_owned)
+            .ok_or(ErrorCode::CONV_ERR)
+    }
+}
+
+impl ConversationHandler for PamConversation<'_> {
+    fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(prompt, false)
+    }
+
+    fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(prompt, true)
+    }
+
+    fn text_info(&mut self, message: &CStr) {
+        self.conversation.message(&message.to_string_lossy());
+    }
+
+    fn error_msg(&mut self, message: &CStr) {
+        log::warn!("PAM: {}", message.to_string_lossy());
+        self.conversation.message(&message.to_string_lossy());
+    }
+}
+
+/// Classify a failed PAM call for the theme
+fn pam_failure(service: &str, user: &str, e: pam_client::Error) -> AuthFailure {
+    match e.code() {
+        // without a service file PAM falls back to the "other" service, which usually denies everything
+        _ if !service_exists(service) => misconfigured(service, e),
+        ErrorCode::AUTH_ERR => AuthFailure::new(UnlockFailure::WrongPassword, "Wrong password"),
+        ErrorCode::USER_UNKNOWN => {
+            AuthFailure::new(UnlockFailure::UnknownUser, format!("Unknown user {user}"))
+        }
+        ErrorCode::ACCT_EXPIRED => {
+            AuthFailure::new(UnlockFailure::AccountExpired, "The account has expired")
+        }
+        ErrorCode::PERM_DENIED | ErrorCode::MAXTRIES => {
+            AuthFailure::new(UnlockFailure::AccountLocked, "The account is locked")
+        }
+        ErrorCode::AUTHINFO_UNAVAIL | ErrorCode::CRED_INSUFFICIENT => AuthFailure::new(
+            UnlockFailure::Unavailable,
+            "The authentication service is unavailable",
+        ),
+        ErrorCode::OPEN_ERR
+        | ErrorCode::SYMBOL_ERR
+        | ErrorCode::SERVICE_ERR
+        | ErrorCode::SYSTEM_ERR
+        | ErrorCode::MODULE_UNKNOWN => misconfigured(service, e),
+        _ => AuthFailure::new(UnlockFailure::Other, e.to_string()),
+    }
+}
+
+fn misconfigured(service: &str, e: pam_client::Error) -> AuthFailure {
+    AuthFailure::new(
+        UnlockFailure::Misconfigured,
+        format!("PAM service {service} is not usable: {e}"),
+    )
+}
+
+/// Whether PAM has a configuration for `service`
+fn service_exists(service: &str) -> bool {
+    ["/etc/pam.d", "/usr/lib/pam.d"]
+        .iter()
+        .any(|dir| Path::new(dir).join(service).exists())
+        || Path::new("/etc/pam.conf").exists()
+}
+
+/// How a transaction of the parallel authentication ended
+#[derive(Debug, PartialEq)]
+enum ParallelOutcome {
+    Success,
+    Retry,
+    GiveUp,
+}
+
+/// Runs the PAM `service`, e.g. a fingerprint reader, without any typed input. Transactions are restarted until one succeeds,
+/// `on_success` is called, or `cancelled` is set because the password was accepted first
+pub fn parallel_auth_thread(
+    service: String,
+    ui: Sender<WindowingMessage>,
+    cancelled: Arc<AtomicBool>,
+    on_success: impl FnOnce(),
+) {
+    log::info!("starting parallel authentication with PAM service '{service}'");
+    run_parallel(
+        || parallel_transaction(&service, &ui, &cancelled),
+        &cancelled,
+        PARALLEL_RETRY_DELAY,
+        on_success,
+    );
+}
+
+/// Time between a failed parallel transaction and the next one, so a stack that fails immediately doesn't spin
+const PARALLEL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn run_parallel(
+    mut transaction: impl FnMut() -> ParallelOutcome,
+    cancelled: &AtomicBool,
+    retry_delay: Duration,
+    on_success: impl FnOnce(),
+) {
+    while !cancelled.load(Ordering::Relaxed) {
+        match transaction() {
+            ParallelOutcome::Success => {
+                if !cancelled.load(Ordering::Relaxed) {
+                    on_success();
+                }
+                return;
+            }
+            ParallelOutcome::Retry => std::thread::sleep(retry_delay),
+            ParallelOutcome::GiveUp => return,
+        }
+    }
+}
+
+/// Conversation of the parallel transaction. Nobody can answer prompts,
+/// but messages like "Place your finger on the reader" are shown
+struct PassiveConversation<'a> {
+    ui: &'a Sender<WindowingMessage>,
+    cancelled: &'a AtomicBool,
+    asked_for_input: bool,
+}
+
+impl PassiveConversation<'_> {
+    fn message(&mut self, message: &CStr) {
+        if !self.cancelled.load(Ordering::Relaxed) {
+            let _ = self.ui.send(WindowingMessage::AuthMessage {
+                message: message.to_string_lossy().into_owned(),
+            });
+        }
+    }
+}
+
+impl ConversationHandler for PassiveConversation<'_> {
+    fn prompt_echo_on(&mut self, _: &CStr) -> Result<CString, ErrorCode> {
+        self.asked_for_input = true;
+        Err(ErrorCode::CONV_ERR)
+    }
+
+    fn prompt_echo_off(&mut self, _: &CStr) -> Result<CString, ErrorCode> {
+        self.asked_for_input = true;
+        Err(ErrorCode::CONV_ERR)
+    }
+
+    fn text_info(&mut self, message: &CStr) {
+        self.message(message);
+    }
+
+    fn error_msg(&mut self, message: &CStr) {
+        self.message(message);
+    }
+}
+
+fn parallel_transaction(
+    service: &str,
+    ui: &Sender<WindowingMessage>,
+    cancelled: &AtomicBool,
+) -> ParallelOutcome {
+    let conversation = PassiveConversation {
+        ui,
+        cancelled,
+        asked_for_input: false,
+    };
+    let username = whoami::username();
+    let mut context = match Context::new(service, Some(&username), conversation) {
+        Ok(context) => context,
+        Err(e) => {
+            log::error!("failed to initialize PAM context for '{service}': {e}");
+            return ParallelOutcome::GiveUp;
+        }
+    };
+
+    let result = context
+        .authenticate(Flag::NONE)
+        .and_then(|()| context.acct_mgmt(Flag::NONE));
+    match result {
+        Ok(()) => ParallelOutcome::Success,
+        Err(_) if context.conversation().asked_for_input => {
+            log::warn!("PAM service '{service}' asks for input, it can't be used for parallel authentication");
+            ParallelOutcome::GiveUp
+        }
+        Err(e) if matches!(e.code(), ErrorCode::AUTH_ERR | ErrorCode::MAXTRIES) => {
+            log::info!("parallel authentication failed: {e}");
+            ParallelOutcome::Retry
+        }
+        Err(e) => {
+            log::warn!("stopping parallel authentication with '{service}': {e}");
+            ParallelOutcome::GiveUp
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use super::{run_parallel, ParallelOutcome};
+
+    #[test]
+    fn test_parallel_retries_until_success() {
+        let cancelled = AtomicBool::new(false);
+        let mut outcomes = vec![ParallelOutcome::Success, ParallelOutcome::Retry];
+        let unlocked = Cell::new(false);
+
+        run_parallel(
+            || outcomes.pop().unwrap(),
+            &cancelled,
+            Duration::ZERO,
+            || unlocked.set(true),
+        );
+        assert!(outcomes.is_empty());
+        assert!(unlocked.get());
+    }
+
+    #[test]
+    fn test_parallel_cancelled_by_password() {
+        let cancelled = AtomicBool::new(false);
+        let unlocked = Cell::new(false);
+
+        // the password is accepted while the fingerprint reader is waiting
+        run_parallel(
+            || {
+                cancelled.store(true, Ordering::Relaxed);
+                ParallelOutcome::Success
+            },
+            &cancelled,
+            Duration::ZERO,
+            || unlocked.set(true),
+        );
+        assert!(!unlocked.get());
+
+        let transactions = Cell::new(0);
+        run_parallel(
+            || {
+                transactions.set(transactions.get() + 1);
+                ParallelOutcome::Retry
+            },
+            &cancelled,
+            Duration::ZERO,
+            || unlocked.set(true),
+        );
+        assert_eq!(transactions.get(), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use std::process::ExitCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,10 +7,29 @@ pub(crate) enum CthulockError {
     Generic(String),
     #[error("")]
     ArgParseFail(#[from] lexopt::Error),
+    #[error("Failed to parse config.toml: {0}")]
+    ConfigParseFail(#[from] toml::de::Error),
     #[error("The following Properties are missing:\n {0:?} \nCheck if they exist and have the correct type")]
     MissingProperties(Vec<String>),
     #[error("The following Callbacks are missing:\n {0:?}")]
     MissingCallbacks(Vec<String>),
+    #[error("Invalid clock format {0:?}")]
+    InvalidClockFormat(String),
     #[error("")]
     WindowingThreadQuit,
+    #[error("The compositor refused to lock the session. Is another screen locker running?")]
+    LockDenied,
+    #[error("The compositor revoked the session lock")]
+    LockRevoked,
+}
+
+impl CthulockError {
+    /// Exit code of the process, so scripts can tell a denied or revoked lock from other failures
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            CthulockError::LockDenied => ExitCode::from(2),
+            CthulockError::LockRevoked => ExitCode::from(3),
+            _ => ExitCode::FAILURE,
+        }
+    }
 }
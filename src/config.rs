@@ -0,0 +1,340 @@
+use chrono::format::{Fixed, Item, Numeric, StrftimeItems};
+use serde::Deserialize;
+use std::{path::PathBuf, time::Duration};
+
+use crate::{args::Args, common::CthulockError, message::OutputInfo, Result};
+
+/// Contents of `config.toml` in the cthulock config directory. Every key is optional
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Components shown on specific outputs instead of the default one
+    #[serde(rename = "output")]
+    pub outputs: Vec<OutputConfig>,
+    pub lock: LockConfig,
+    pub auth: AuthConfig,
+    pub hooks: HooksConfig,
+    pub clock: ClockConfig,
+}
+
+/// The `[auth]` table
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub backend: AuthBackend,
+    /// PAM service in /etc/pam.d used for the password
+    pub service: String,
+    /// `user:hash` lines checked by the file backend
+    pub password_file: Option<PathBuf>,
+    /// Users other than the one running cthulock that may unlock with their own password, e.g. an administrator of a kiosk
+    pub allowed_users: Vec<String>,
+    /// PAM service that doesn't need typed input, e.g. for a fingerprint reader. Runs alongside the password while locked
+    pub parallel_service: Option<String>,
+    /// Failed attempts allowed before further attempts are delayed
+    pub throttle_after: u32,
+    /// Delay after the first throttled attempt, doubled after every further one. 0 disables throttling
+    pub throttle_seconds: u64,
+    pub max_throttle_seconds: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            backend: AuthBackend::Pam,
+            service: "cthulock".to_owned(),
+            password_file: None,
+            allowed_users: Vec::new(),
+            parallel_service: None,
+            throttle_after: 3,
+            throttle_seconds: 5,
+            max_throttle_seconds: 300,
+        }
+    }
+}
+
+/// Where passwords are checked
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    Pam,
+    /// crypt(3) hashes in `password_file`
+    File,
+}
+
+/// The `[hooks]` table. Commands are run with `sh -c`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Run once the compositor confirmed the lock
+    pub on_lock: Option<String>,
+    pub on_unlock: Option<String>,
+    pub on_auth_failure: Option<String>,
+    /// Run when the compositor refused to lock the session, after all retries
+    pub on_lock_denied: Option<String>,
+    /// Hooks still running after this are killed
+    pub timeout_seconds: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_lock: None,
+            on_unlock: None,
+            on_auth_failure: None,
+            on_lock_denied: None,
+            timeout_seconds: 5,
+        }
+    }
+}
+
+/// The `[lock]` table
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockConfig {
+    /// How often to ask the compositor again if it refuses to lock the session
+    pub retries: u32,
+    /// Delay before the first retry, doubled after every attempt
+    pub retry_delay_ms: u64,
+    /// Unlock on `loginctl unlock-session` and delay suspending until the lock screen is shown
+    pub logind: bool,
+    /// Any input within this time after the session was locked unlocks it without a password
+    pub grace_seconds: f64,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            retry_delay_ms: 500,
+            logind: false,
+            grace_seconds: 0.0,
+        }
+    }
+}
+
+impl LockConfig {
+    pub fn grace_period(&self) -> Duration {
+        Duration::try_from_secs_f64(self.grace_seconds).unwrap_or_default()
+    }
+
+    /// Delay before retry number `attempt`, starting at 0
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_delay_ms.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+/// The `[clock]` table, how `clock_text` is formatted
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    /// strftime format, replaces the one picked by `hour12` and `seconds`
+    pub format: Option<String>,
+    /// 3:04 PM instead of 15:04
+    pub hour12: bool,
+    pub seconds: bool,
+    /// strftime format of `date_text`
+    pub date_format: Option<String>,
+    /// Locale of month and weekday names and of `%x` and similar, like `de_DE.UTF-8`. Defaults to `LC_TIME`
+    pub locale: Option<String>,
+}
+
+impl ClockConfig {
+    /// The strftime format of `clock_text`
+    pub fn format(&self) -> &str {
+        match (&self.format, self.hour12, self.seconds) {
+            (Some(format), _, _) => format,
+            (None, false, false) => "%H:%M",
+            (None, false, true) => "%H:%M:%S",
+            (None, true, false) => "%-I:%M %p",
+            (None, true, true) => "%-I:%M:%S %p",
+        }
+    }
+
+    /// The strftime format of `date_text`
+    pub fn date_format(&self) -> &str {
+        self.date_format.as_deref().unwrap_or("%A, %x")
+    }
+
+    /// Whether the format shows seconds, so the clock has to change every second instead of every minute
+    pub fn shows_seconds(&self) -> bool {
+        StrftimeItems::new(self.format()).any(|item| {
+            matches!(
+                item,
+                Item::Numeric(
+                    Numeric::Second | Numeric::Nanosecond | Numeric::Timestamp,
+                    _
+                ) | Item::Fixed(
+                    Fixed::Nanosecond
+                        | Fixed::Nanosecond3
+                        | Fixed::Nanosecond6
+                        | Fixed::Nanosecond9
+                        | Fixed::RFC2822
+                        | Fixed::RFC3339
+                )
+            )
+        })
+    }
+
+    /// Formatting with an invalid format panics, so it is checked before locking
+    pub fn validate(&self) -> Result<()> {
+        for format in [self.format(), self.date_format()] {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(CthulockError::InvalidClockFormat(format.to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `[[output]]` entry selecting the component shown on matching outputs.
+/// All of `name`, `description`, `make` and `model` that are set have to match
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Connector name like `DP-2`, requires wl_output version 4
+    pub name: Option<String>,
+    /// Human readable description, requires wl_output version 4
+    pub description: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+
+    /// Name of an exported component. Looked up in `file` if set, otherwise in style.slint
+    pub component: Option<String>,
+    /// Slint file relative to the config directory
+    pub file: Option<PathBuf>,
+    /// Secondary screens don't need a password field. Keyboard input is sent to a primary screen instead
+    #[serde(default)]
+    pub secondary: bool,
+}
+
+impl OutputConfig {
+    pub fn matches(&self, info: &OutputInfo) -> bool {
+        let field_matches = |expected: &Option<String>, actual: &Option<String>| {
+            expected.is_none() || expected == actual
+        };
+        field_matches(&self.name, &info.name)
+            && field_matches(&self.description, &info.description)
+            && field_matches(&self.make, &info.make)
+            && field_matches(&self.model, &info.model)
+    }
+}
+
+pub fn load_config_or_default(args: &Args) -> Result<Config> {
+    let config = load_config();
+    if let Err(e) = config {
+        if args.fallback_config {
+            log::error!("Loading config.toml failed. Using the default config. Errors: \n{e}");
+            Ok(Config::default())
+        } else {
+            Err(e)
+        }
+    } else {
+        config
+    }
+}
+
+fn load_config() -> Result<Config> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("cthulock");
+
+    let Some(config_path) = xdg_dirs.find_config_file("config.toml") else {
+        return Ok(Config::default());
+    };
+
+    let config =
+        std::fs::read_to_string(config_path).map_err(|e| CthulockError::Generic(e.to_string()))?;
+    parse_config(&config)
+}
+
+fn parse_config(config: &str) -> Result<Config> {
+    let config: Config = toml::from_str(config)?;
+    config.clock.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{message::OutputInfo, Result};
+    use std::time::Duration;
+
+    use super::{parse_config, ClockConfig};
+
+    #[test]
+    fn test_output_matching() -> Result<()> {
+        let config = parse_config(
+            r#"
+            [[output]]
+            name = "DP-2"
+            component = "SidePanel"
+            secondary = true
+
+            [[output]]
+            make = "Dell Inc."
+            model = "U2720Q"
+            file = "dell.slint"
+            "#,
+        )?;
+        assert_eq!(config.outputs.len(), 2);
+
+        let side = OutputInfo {
+            name: Some("DP-2".to_owned()),
+            ..Default::default()
+        };
+        let dell = OutputInfo {
+            name: Some("DP-1".to_owned()),
+            make: Some("Dell Inc.".to_owned()),
+            model: Some("U2720Q".to_owned()),
+            ..Default::default()
+        };
+        assert!(config.outputs[0].matches(&side));
+        assert!(!config.outputs[0].matches(&dell));
+        assert!(config.outputs[1].matches(&dell));
+        assert!(!config.outputs[1].matches(&side));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_retry_delay() -> Result<()> {
+        let config = parse_config("")?;
+        assert_eq!(config.lock.retries, 0);
+
+        let config = parse_config(
+            r#"
+            [lock]
+            retries = 3
+            retry_delay_ms = 100
+            "#,
+        )?;
+        assert_eq!(config.lock.retries, 3);
+        assert_eq!(config.lock.retry_delay(0), Duration::from_millis(100));
+        assert_eq!(config.lock.retry_delay(2), Duration::from_millis(400));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_format() -> Result<()> {
+        let config = parse_config("")?;
+        assert_eq!(config.clock.format(), "%H:%M");
+        assert!(!config.clock.shows_seconds());
+
+        let config = parse_config(
+            r#"
+            [clock]
+            hour12 = true
+            seconds = true
+            "#,
+        )?;
+        assert_eq!(config.clock.format(), "%-I:%M:%S %p");
+        assert!(config.clock.shows_seconds());
+
+        // seconds hidden in a composite specifier
+        let clock = ClockConfig {
+            format: Some("%a %T".to_owned()),
+            ..Default::default()
+        };
+        assert!(clock.shows_seconds());
+
+        assert!(parse_config("[clock]\nformat = \"%H:%Q\"").is_err());
+        assert!(parse_config("[clock]\ndate_format = \"%Q\"").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    process::{Command, Stdio},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::config::HooksConfig;
+
+/// Events the `[hooks]` table can run a command for
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent {
+    Lock,
+    Unlock,
+    AuthFailure,
+    LockDenied,
+}
+
+impl HookEvent {
+    /// Value of `$CTHULOCK_EVENT`
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Lock => "lock",
+            HookEvent::Unlock => "unlock",
+            HookEvent::AuthFailure => "auth_failure",
+            HookEvent::LockDenied => "lock_denied",
+        }
+    }
+}
+
+/// Runs the commands configured in the `[hooks]` table
+#[derive(Clone)]
+pub struct Hooks {
+    config: HooksConfig,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig) -> Self {
+        Self { config }
+    }
+
+    /// Run the command for `event` with `sh -c` in the background. It gets `$CTHULOCK_EVENT` and `env`,
+    /// and is killed once `timeout_seconds` passed. Join the handle to wait for it
+    pub fn run(&self, event: HookEvent, env: &[(&str, String)]) -> Option<JoinHandle<()>> {
+        let command = match event {
+            HookEvent::Lock => &self.config.on_lock,
+            HookEvent::Unlock => &self.config.on_unlock,
+            HookEvent::AuthFailure => &self.config.on_auth_failure,
+            HookEvent::LockDenied => &self.config.on_lock_denied,
+        }
+        .clone()?;
+
+        log::info!("running {} hook: {command}", event.name());
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("CTHULOCK_EVENT", event.name())
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                log::error!("failed to run {} hook: {e}", event.name());
+                return None;
+            }
+        };
+
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        Some(std::thread::spawn(move || {
+            let deadline = Instant::now() + timeout;
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => {
+                        if !status.success() {
+                            log::warn!("hook '{command}' failed: {status}");
+                        }
+                        return;
+                    }
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(50))
+                    }
+                    Ok(None) => {
+                        log::warn!("hook '{command}' timed out after {timeout:?}, killing it");
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return;
+                    }
+                    Err(e) => {
+                        log::error!("failed to wait for hook '{command}': {e}");
+                        return;
+                    }
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{HookEvent, Hooks};
+    use crate::config::HooksConfig;
+
+    #[test]
+    fn test_hook_environment() {
+        let path = std::env::temp_dir().join(format!("cthulock-hook-{}", std::process::id()));
+        let hooks = Hooks::new(HooksConfig {
+            on_auth_failure: Some(format!(
+                "echo $CTHULOCK_EVENT $CTHULOCK_FAILED_ATTEMPTS > {}",
+                path.display()
+            )),
+            ..Default::default()
+        });
+
+        hooks
+            .run(
+                HookEvent::AuthFailure,
+                &[("CTHULOCK_FAILED_ATTEMPTS", 3.to_string())],
+            )
+            .unwrap()
+            .join()
+            .unwrap();
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(output, "auth_failure 3\n");
+        assert!(hooks.run(HookEvent::Unlock, &[]).is_none());
+    }
+
+    #[test]
+    fn test_hook_timeout() {
+        let hooks = Hooks::new(HooksConfig {
+            on_lock: Some("sleep 10".to_owned()),
+            timeout_seconds: 0,
+            ..Default::default()
+        });
+
+        let start = Instant::now();
+        hooks.run(HookEvent::Lock, &[]).unwrap().join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
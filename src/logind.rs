@@ -0,0 +1,392 @@
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    sync::{Arc, Mutex},
+};
+
+use zbus::{
+    blocking::{Connection, MessageIterator},
+    message::Type,
+    zvariant::{self, OwnedObjectPath},
+    MatchRule,
+};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const DBUS: &str = "org.freedesktop.DBus";
+
+/// Signals from logind concerning the locked session
+#[derive(Debug, PartialEq)]
+pub enum LogindEvent {
+    /// `loginctl lock-session`
+    Lock,
+    /// `loginctl unlock-session`, the session has to be unlocked without a password
+    Unlock,
+    /// The system is about to suspend (`true`) or has resumed (`false`)
+    PrepareForSleep(bool),
+}
+
+/// The logind session cthulock runs in
+pub struct Logind {
+    connection: Connection,
+    session: OwnedObjectPath,
+    // delays suspending until the lock screen is shown
+    sleep_inhibitor: Option<OwnedFd>,
+}
+
+impl Logind {
+    /// Connect to logind on the system bus
+    pub fn connect() -> zbus::Result<Self> {
+        Self::with_connection(Connection::system()?)
+    }
+
+    /// Find the session of this process through `connection`, which has to provide logind
+    pub fn with_connection(connection: Connection) -> zbus::Result<Self> {
+        let reply = match std::env::var("XDG_SESSION_ID") {
+            Ok(id) => connection.call_method(
+                Some(LOGIND),
+                MANAGER_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetSession",
+                &id,
+            )?,
+            Err(_) => connection.call_method(
+                Some(LOGIND),
+                MANAGER_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetSessionByPID",
+                &std::process::id(),
+            )?,
+        };
+        let session: OwnedObjectPath = reply.body().deserialize()?;
+        log::debug!("logind session is {}", session.as_str());
+
+        Ok(Self {
+            connection,
+            session,
+            sleep_inhibitor: None,
+        })
+    }
+
+    /// Take a delay inhibitor, so the system doesn't suspend before `release_sleep` is called
+    pub fn inhibit_sleep(&mut self) -> zbus::Result<()> {
+        let reply = self.connection.call_method(
+            Some(LOGIND),
+            MANAGER_PATH,
+            Some(MANAGER_INTERFACE),
+            "Inhibit",
+            &(
+                "sleep",
+                "cthulock",
+                "Showing the lock screen before suspending",
+                "delay",
+            ),
+        )?;
+        let fd: OwnedFd = reply.body().deserialize::<zvariant::OwnedFd>()?.into();
+        // zbus receives fds without FD_CLOEXEC. Hook commands must not inherit it, or they would delay suspending as well
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+        unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) };
+        self.sleep_inhibitor = Some(fd);
+        Ok(())
+    }
+
+    /// Let the system suspend. Does nothing without an inhibitor
+    pub fn release_sleep(&mut self) {
+        if self.sleep_inhibitor.take().is_some() {
+            log::debug!("released sleep inhibitor");
+        }
+    }
+
+    /// Tell logind whether the session is locked, shown e.g. by `loginctl show-session`
+    pub fn set_locked_hint(&self, locked: bool) {
+        let result = self.connection.call_method(
+            Some(LOGIND),
+            &self.session,
+            Some(SESSION_INTERFACE),
+            "SetLockedHint",
+            &locked,
+        );
+        if let Err(e) = result {
+            log::warn!("failed to set the locked hint of the session: {e}");
+        }
+    }
+
+    /// The unique name of the connection owning the logind name, `None` if logind isn't running
+    fn logind_owner(&self) -> Option<String> {
+        let owner = self
+            .connection
+            .call_method(
+                Some(DBUS),
+                "/org/freedesktop/DBus",
+                Some(DBUS),
+                "GetNameOwner",
+                &LOGIND,
+            )
+            .and_then(|reply| reply.body().deserialize::<String>());
+        match owner {
+            Ok(owner) => Some(owner),
+            Err(e) => {
+                log::warn!("failed to find the owner of {LOGIND}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Call `on_event` from a new thread for every signal concerning the session until the connection is closed
+    pub fn listen(
+        &self,
+        mut on_event: impl FnMut(LogindEvent) + Send + 'static,
+    ) -> zbus::Result<()> {
+        // any peer can send signals directly to cthulock and the sender of a match rule doesn't stop them,
+        // so only signals from the connection currently owning the logind name are trusted
+        let owner_rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(DBUS)?
+            .interface(DBUS)?
+            .member("NameOwnerChanged")?
+            .arg(0, LOGIND)?
+            .build();
+        let owner_changes = MessageIterator::for_match_rule(owner_rule, &self.connection, None)?;
+        let owner = Arc::new(Mutex::new(self.logind_owner()));
+
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(LOGIND)?
+            .build();
+        let messages = MessageIterator::for_match_rule(rule, &self.connection, None)?;
+        let session = self.session.clone();
+
+        let new_owner = owner.clone();
+        std::thread::spawn(move || {
+            for message in owner_changes.flatten() {
+                if message.header().sender().map(|sender| sender.as_str()) != Some(DBUS) {
+                    continue;
+                }
+                if let Ok((name, _, owner)) =
+                    message.body().deserialize::<(String, String, String)>()
+                {
+                    if name == LOGIND {
+                        log::info!("{LOGIND} is now owned by '{owner}'");
+                        *new_owner.lock().unwrap() = (!owner.is_empty()).then_some(owner);
+                    }
+                }
+            }
+        });
+
+        std::thread::spawn(move || {
+            for message in messages {
+                let Ok(message) = message else {
+                    continue;
+                };
+                let header = message.header();
+                let (Some(interface), Some(member), Some(path)) =
+                    (header.interface(), header.member(), header.path())
+                else {
+                    continue;
+                };
+                let sender = header.sender().map(|sender| sender.as_str());
+                if sender.is_none() || sender != owner.lock().unwrap().as_deref() {
+                    log::warn!(
+                        "ignoring {member} signal from {}, it doesn't own {LOGIND}",
+                        sender.unwrap_or("an unknown sender")
+                    );
+                    continue;
+                }
+
+                let event = match (interface.as_str(), member.as_str()) {
+                    (SESSION_INTERFACE, "Lock") if path.as_str() == session.as_str() => {
+                        LogindEvent::Lock
+                    }
+                    (SESSION_INTERFACE, "Unlock") if path.as_str() == session.as_str() => {
+                        LogindEvent::Unlock
+                    }
+                    (MANAGER_INTERFACE, "PrepareForSleep") => {
+                        match message.body().deserialize::<bool>() {
+                            Ok(start) => LogindEvent::PrepareForSleep(start),
+                            Err(e) => {
+                                log::warn!("invalid PrepareForSleep signal: {e}");
+                                continue;
+                            }
+                        }
+                    }
+                    _ => continue,
+                };
+                log::info!("logind: {event:?}");
+                on_event(event);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use zbus::{
+        blocking::connection,
+        interface,
+        zvariant::{self, OwnedObjectPath},
+    };
+
+    use super::{Logind, LogindEvent};
+
+    /// A private bus, killed when dropped
+    struct DbusDaemon(Child);
+
+    impl Drop for DbusDaemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    struct FakeManager;
+
+    #[interface(name = "org.freedesktop.login1.Manager")]
+    impl FakeManager {
+        fn get_session(&self, _id: &str) -> OwnedObjectPath {
+            OwnedObjectPath::try_from("/org/freedesktop/login1/session/test").unwrap()
+        }
+
+        #[zbus(name = "GetSessionByPID")]
+        fn get_session_by_pid(&self, _pid: u32) -> OwnedObjectPath {
+            self.get_session("")
+        }
+
+        fn inhibit(&self, _what: &str, _who: &str, _why: &str, _mode: &str) -> zvariant::OwnedFd {
+            let file = std::fs::File::open("/dev/null").unwrap();
+            std::os::fd::OwnedFd::from(file).into()
+        }
+    }
+
+    struct FakeSession;
+
+    #[interface(name = "org.freedesktop.login1.Session")]
+    impl FakeSession {
+        fn set_locked_hint(&self, _locked: bool) {}
+    }
+
+    #[test]
+    fn test_logind_signals() {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the test needs dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let _daemon = DbusDaemon(daemon);
+
+        let logind = connection::Builder::address(address.trim())
+            .unwrap()
+            .name("org.freedesktop.login1")
+            .unwrap()
+            .serve_at("/org/freedesktop/login1", FakeManager)
+            .unwrap()
+            .serve_at("/org/freedesktop/login1/session/test", FakeSession)
+            .unwrap()
+            .build()
+            .unwrap();
+        let client = connection::Builder::address(address.trim())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut session = Logind::with_connection(client).unwrap();
+        session.inhibit_sleep().unwrap();
+        assert!(session.sleep_inhibitor.is_some());
+        session.set_locked_hint(true);
+
+        let (sender, events) = mpsc::channel();
+        session
+            .listen(move |event| sender.send(event).unwrap())
+            .unwrap();
+
+        logind
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/login1/session/test",
+                "org.freedesktop.login1.Session",
+                "Unlock",
+                &(),
+            )
+            .unwrap();
+        // signals of other sessions are ignored
+        logind
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/login1/session/other",
+                "org.freedesktop.login1.Session",
+                "Lock",
+                &(),
+            )
+            .unwrap();
+        logind
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/login1",
+                "org.freedesktop.login1.Manager",
+                "PrepareForSleep",
+                &true,
+            )
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(events.recv_timeout(timeout), Ok(LogindEvent::Unlock));
+        assert_eq!(
+            events.recv_timeout(timeout),
+            Ok(LogindEvent::PrepareForSleep(true))
+        );
+
+        // another peer sending Unlock directly to cthulock must not unlock the session
+        let attacker = connection::Builder::address(address.trim())
+            .unwrap()
+            .build()
+            .unwrap();
+        let destination = session.connection.unique_name().unwrap().to_owned();
+        attacker
+            .emit_signal(
+                Some(destination.as_str()),
+                "/org/freedesktop/login1/session/test",
+                "org.freedesktop.login1.Session",
+                "Unlock",
+                &(),
+            )
+            .unwrap();
+        attacker
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/login1/session/test",
+                "org.freedesktop.login1.Session",
+                "Unlock",
+                &(),
+            )
+            .unwrap();
+        logind
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/login1",
+                "org.freedesktop.login1.Manager",
+                "PrepareForSleep",
+                &false,
+            )
+            .unwrap();
+        assert_eq!(
+            events.recv_timeout(timeout),
+            Ok(LogindEvent::PrepareForSleep(false))
+        );
+        assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+        session.release_sleep();
+        assert!(session.sleep_inhibitor.is_none());
+    }
+}
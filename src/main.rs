@@ -1,8 +1,10 @@
-use std::{sync::mpsc, thread};
+use std::{process::ExitCode, sync::mpsc, thread};
 
 use crate::{
     common::CthulockError,
-    message::{UiMessage, WindowingMessage},
+    config::load_config_or_default,
+    message::{AuthRequest, UiMessage, WindowingMessage},
+    ready::{daemonize, ReadyNotifier},
     style::load_style_or_fallback,
     ui::ui_thread,
     windowing_thread::windowing_thread,
@@ -11,32 +13,80 @@ use crate::{
 type Result<T> = std::result::Result<T, CthulockError>;
 
 mod args;
+mod auth;
 mod common;
+mod config;
+mod hooks;
+mod logind;
 mod message;
+mod ready;
+mod secret;
 mod style;
 mod ui;
 mod windowing_thread;
 
-// TODO: Better Error formatting
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     init_logger();
 
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // TODO: Better Error formatting
+            eprintln!("Error: {e:?}");
+            e.exit_code()
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let args = args::parse_args().map_err(CthulockError::ArgParseFail)?;
 
-    let style = load_style_or_fallback(&args)?;
+    let mut ready = ReadyNotifier::new(args.ready_fd)?;
+    if args.daemonize {
+        ready.add_fd(daemonize()?);
+    }
+
+    let mut config = load_config_or_default(&args)?;
+    if let Some(service) = args.pam_service.clone() {
+        config.auth.service = service;
+    }
+    if let Some(format) = args.clock_format.clone() {
+        config.clock.format = Some(format);
+        config.clock.validate()?;
+    }
+    let style = load_style_or_fallback(&args, &config)?;
+    let authenticator = auth::authenticator(&config.auth)?;
+    let clock = config.clock.clone();
 
     let (sender_to_render, receiver_from_windowing) = mpsc::channel::<WindowingMessage>();
     let (sender_to_windowing, receiver_from_render) = mpsc::channel::<UiMessage>();
+    let (sender_to_auth, receiver_from_ui) = mpsc::channel::<AuthRequest>();
 
-    thread::spawn(move || {
-        if windowing_thread(sender_to_render.clone(), receiver_from_render).is_err() {
-            sender_to_render.send(WindowingMessage::Quit).unwrap();
+    let windowing = thread::spawn(move || {
+        let result = windowing_thread(
+            sender_to_render.clone(),
+            receiver_from_render,
+            receiver_from_ui,
+            config,
+            authenticator,
+            ready,
+        );
+        if result.is_err() {
+            let _ = sender_to_render.send(WindowingMessage::Quit);
         }
+        result
     });
 
-    ui_thread(style, sender_to_windowing, receiver_from_windowing)?;
+    ui_thread(
+        style,
+        args.software_rendering,
+        clock,
+        sender_to_windowing,
+        sender_to_auth,
+        receiver_from_windowing,
+    )?;
 
-    Ok(())
+    windowing.join().unwrap()
 }
 
 fn init_logger() {
@@ -1,19 +1,152 @@
+use crate::{secret::Secret, windowing_thread::LockSurface};
 use slint::platform::WindowEvent;
-use wayland_client::backend::ObjectId;
+use std::time::Duration;
+use wayland_client::{backend::ObjectId, protocol::wl_shm};
+
+/// Identifies a locked output by the registry name of its `wl_output` global
+pub type OutputId = u32;
+
+/// Properties the compositor sent for an output, used to select the component shown on it
+#[derive(Debug, Clone, Default)]
+pub struct OutputInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+}
 
 #[derive(Debug)]
 pub enum WindowingMessage {
     SurfaceReady {
+        output: OutputId,
+        info: Box<OutputInfo>,
         display_id: ObjectId,
-        surface_id: ObjectId,
+        surface: Box<LockSurface>,
+        /// Used to render into shared memory when OpenGL is not available
+        shm: wl_shm::WlShm,
+        /// Size in surface-local coordinates
         size: (u32, u32),
+        scale: f32,
+        /// Acked by the UI thread before the first frame, see `LockSurface::configure`
+        serial: u32,
+    },
+    /// The lock surface was reconfigured, e.g. with a new size after rotating the output or changing its mode
+    Resized {
+        output: OutputId,
+        size: (u32, u32),
+        serial: u32,
+    },
+    /// The compositor prefers a different scale factor for the output
+    ScaleChanged {
+        output: OutputId,
+        scale: f32,
+    },
+    /// The output has been unplugged. Its render context has to be dropped and the windowing thread notified with `UiMessage::OutputReleased`
+    OutputRemoved {
+        output: OutputId,
+    },
+    /// The session was locked. Until `remaining` passed, any input unlocks it
+    GracePeriod {
+        remaining: Duration,
+    },
+    /// The compositor is ready for a new frame on the output
+    FrameDone {
+        output: OutputId,
+    },
+    SlintWindowEvent {
+        output: OutputId,
+        event: WindowEvent,
+    },
+    /// A lock key was toggled or the keyboard layout changed
+    KeyboardState {
+        caps_lock: bool,
+        num_lock: bool,
+        /// xkb name of the active layout, e.g. "English (US)"
+        layout: String,
+    },
+    /// The keymap changed, listing the names of its layouts
+    KeyboardLayouts {
+        layouts: Vec<String>,
+    },
+    /// PAM asks for more input, e.g. a one-time password or a new password after the old one expired
+    AuthPrompt {
+        prompt: String,
+        /// The answer must not be shown while typing
+        secret: bool,
+    },
+    /// An informational or error message from PAM
+    AuthMessage {
+        message: String,
+    },
+    UnlockFailed {
+        reason: UnlockFailure,
+        /// Describes the failure to the user, e.g. "The account has expired"
+        message: String,
+        /// Failed attempts since cthulock was started
+        failed_attempts: u32,
+        /// The next attempt will be rejected until this time has passed
+        retry_after: Duration,
     },
-    SlintWindowEvent(WindowEvent),
-    UnlockFailed,
     Quit,
 }
 
+/// Why an unlock attempt was rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnlockFailure {
+    WrongPassword,
+    UnknownUser,
+    /// The user is neither the one running cthulock nor in `allowed_users`
+    NotAllowed,
+    AccountExpired,
+    /// Locked by an administrator or after too many failures, e.g. by pam_faillock
+    AccountLocked,
+    /// The password expired and setting a new one failed
+    PasswordChangeFailed,
+    /// The attempt was made before `retry_after` passed
+    Throttled,
+    /// A service PAM relies on, e.g. LDAP, is not reachable
+    Unavailable,
+    /// The PAM service is missing or broken
+    Misconfigured,
+    Other,
+}
+
+impl UnlockFailure {
+    /// Value of the `error_kind` property
+    pub fn kind(self) -> &'static str {
+        match self {
+            UnlockFailure::WrongPassword => "wrong_password",
+            UnlockFailure::UnknownUser => "unknown_user",
+            UnlockFailure::NotAllowed => "not_allowed",
+            UnlockFailure::AccountExpired => "account_expired",
+            UnlockFailure::AccountLocked => "account_locked",
+            UnlockFailure::PasswordChangeFailed => "password_change_failed",
+            UnlockFailure::Throttled => "throttled",
+            UnlockFailure::Unavailable => "unavailable",
+            UnlockFailure::Misconfigured => "misconfigured",
+            UnlockFailure::Other => "other",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum UiMessage {
-    UnlockWithPassword { password: String },
+    OutputReleased {
+        output: OutputId,
+    },
+    /// Translate keys with the layout at this index of the keymap, chosen with the `switch_layout` callback
+    SwitchLayout {
+        layout: u32,
+    },
+}
+
+/// Sent from the UI thread to the authentication thread
+#[derive(Debug)]
+pub enum AuthRequest {
+    /// Starts a PAM transaction, or answers its current prompt
+    Submit {
+        password: Secret,
+        /// Set by themes with a `username` property, otherwise the user running cthulock authenticates
+        username: Option<String>,
+    },
 }
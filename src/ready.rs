@@ -0,0 +1,118 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+};
+
+use crate::{common::CthulockError, Result};
+
+/// Tells whoever started cthulock that the session is locked, through `--ready-fd`,
+/// the pipe of `--daemonize` and `$NOTIFY_SOCKET`
+#[derive(Default)]
+pub struct ReadyNotifier {
+    fds: Vec<File>,
+    notify_socket: Option<String>,
+}
+
+impl ReadyNotifier {
+    pub fn new(ready_fd: Option<RawFd>) -> Result<Self> {
+        let mut notifier = Self {
+            notify_socket: std::env::var("NOTIFY_SOCKET").ok(),
+            ..Default::default()
+        };
+        if let Some(fd) = ready_fd {
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+            if flags == -1 {
+                return Err(CthulockError::Generic(format!(
+                    "--ready-fd {fd} is not an open file descriptor"
+                )));
+            }
+            // hook commands must not inherit it, whoever waits for it to close would wait for them as well
+            unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) };
+            notifier.fds.push(unsafe { File::from_raw_fd(fd) });
+        }
+        Ok(notifier)
+    }
+
+    pub fn add_fd(&mut self, fd: OwnedFd) {
+        self.fds.push(fd.into());
+    }
+
+    /// Write a newline to every ready fd and close it, then send `READY=1` to the service manager
+    pub fn notify(self) {
+        log::info!("session is locked, notifying readiness");
+
+        for mut fd in self.fds {
+            if let Err(e) = fd.write_all(b"\n") {
+                log::warn!("failed to write to ready fd: {e}");
+            }
+        }
+
+        if let Some(path) = self.notify_socket {
+            if let Err(e) = sd_notify(&path, "READY=1") {
+                log::warn!("failed to notify the service manager: {e}");
+            }
+        }
+    }
+}
+
+fn sd_notify(path: &str, state: &str) -> std::io::Result<()> {
+    // a leading '@' stands for a socket in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Fork into the background. The parent exits once the child has locked the session, or with the child's exit code if it quits before.
+/// Returns the fd the child has to notify. Must be called before any thread is spawned
+pub fn daemonize() -> Result<OwnedFd> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(CthulockError::Generic(format!(
+            "Failed to create pipe: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let (read_end, write_end) =
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => Err(CthulockError::Generic(format!(
+            "Failed to fork: {}",
+            std::io::Error::last_os_error()
+        ))),
+        0 => {
+            drop(read_end);
+            unsafe { libc::setsid() };
+            Ok(write_end)
+        }
+        child => {
+            drop(write_end);
+            let mut ready = [0];
+            if File::from(read_end)
+                .read(&mut ready)
+                .is_ok_and(|read| read > 0)
+            {
+                std::process::exit(0);
+            }
+
+            // the pipe was closed without a notification, so the child exited
+            let mut status = 0;
+            unsafe { libc::waitpid(child, &mut status, 0) };
+            let code = if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                1
+            };
+            std::process::exit(code);
+        }
+    }
+}
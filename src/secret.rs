@@ -0,0 +1,100 @@
+use std::{
+    alloc::{self, Layout},
+    ffi::CStr,
+    fmt::Debug,
+    ptr::NonNull,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+/// A password on its way from the UI to the authenticator. The memory is locked so it is never swapped out,
+/// and wiped when dropped
+pub struct Secret {
+    // NUL terminated, so it can be handed to C without another copy.
+    // mlock works on whole pages and doesn't nest, so every secret gets pages of its own,
+    // otherwise dropping one would unlock the others sharing its pages
+    ptr: NonNull<u8>,
+    // including the NUL
+    len: usize,
+    layout: Layout,
+}
+
+// the memory is owned by the secret and only read through `&self`
+unsafe impl Send for Secret {}
+
+impl Secret {
+    pub fn new(secret: &str) -> Self {
+        let len = secret.len() + 1;
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => 4096,
+        };
+        let layout = Layout::from_size_align(len.next_multiple_of(page_size), page_size)
+            .expect("secret too large");
+        let Some(ptr) = NonNull::new(unsafe { alloc::alloc_zeroed(layout) }) else {
+            alloc::handle_alloc_error(layout);
+        };
+        unsafe {
+            ptr.as_ptr()
+                .copy_from_nonoverlapping(secret.as_ptr(), secret.len())
+        };
+        if unsafe { libc::mlock(ptr.as_ptr().cast(), layout.size()) } != 0 {
+            log::debug!(
+                "failed to lock secret in memory: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Self { ptr, len, layout }
+    }
+
+    /// `None` if the secret contains a NUL byte
+    pub fn as_c_str(&self) -> Option<&CStr> {
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) };
+        CStr::from_bytes_with_nul(bytes).ok()
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        let ptr = self.ptr.as_ptr();
+        for i in 0..self.layout.size() {
+            // volatile, so the writes aren't optimized away as dead stores
+            unsafe { ptr.add(i).write_volatile(0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+        unsafe {
+            libc::munlock(ptr.cast(), self.layout.size());
+            alloc::dealloc(ptr, self.layout);
+        }
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    fn test_secret() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(secret.as_c_str().unwrap().to_bytes(), b"hunter2");
+        assert_eq!(Secret::new("").as_c_str().unwrap().to_bytes(), b"");
+        assert!(Secret::new("hunter\0two").as_c_str().is_none());
+
+        // secrets never share a page
+        let other = Secret::new("123456");
+        let page_size = secret.layout.align();
+        assert_eq!(secret.ptr.as_ptr() as usize % page_size, 0);
+        assert_eq!(other.ptr.as_ptr() as usize % page_size, 0);
+    }
+}
@@ -5,19 +5,67 @@ use std::path::PathBuf;
 use crate::{
     args::Args,
     common::CthulockError,
-    ui::slint_types::{OptionalProperties, RequiredCallbacks, RequiredProperties, SlintProperty},
+    config::{Config, OutputConfig},
+    message::OutputInfo,
+    ui::slint_types::{
+        OptionalCallbacks, OptionalProperties, RequiredCallbacks, RequiredProperties, SlintProperty,
+    },
     Result,
 };
 
 pub(crate) const FALLBACK_STYLE: &str = include_str!("../docs/fallback_config.slint");
 
-pub fn load_style_or_fallback(args: &Args) -> Result<ComponentDefinition> {
-    let style = get_style_and_include_paths()
-        .and_then(|(style_string, config_dirs)| load_style(style_string, config_dirs, false));
+/// Whether a component can be used to unlock the screen or only decorates an output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScreenKind {
+    Primary,
+    Secondary,
+}
+
+/// A compiled and validated Slint component
+#[derive(Clone)]
+pub struct StyleComponent {
+    pub definition: ComponentDefinition,
+    pub kind: ScreenKind,
+}
+
+/// All components used for locking the screen
+pub struct Style {
+    /// Shown on every output without a matching `[[output]]` entry
+    pub default: StyleComponent,
+    pub outputs: Vec<(OutputConfig, StyleComponent)>,
+}
+
+impl Style {
+    /// Whether any of the components has `property`
+    pub fn uses_property(&self, property: &str) -> bool {
+        std::iter::once(&self.default)
+            .chain(self.outputs.iter().map(|(_, component)| component))
+            .any(|component| {
+                component
+                    .definition
+                    .properties()
+                    .any(|(name, _)| name == property)
+            })
+    }
+
+    /// Select the component for an output. The first matching `[[output]]` entry wins
+    pub fn component_for(&self, info: &OutputInfo) -> &StyleComponent {
+        self.outputs
+            .iter()
+            .find(|(output, _)| output.matches(info))
+            .map_or(&self.default, |(_, component)| component)
+    }
+}
+
+pub fn load_style_or_fallback(args: &Args, config: &Config) -> Result<Style> {
+    let style = get_style_and_include_paths().and_then(|(style_string, config_dirs)| {
+        load_style(style_string, config_dirs, &config.outputs, false)
+    });
     if let Err(e) = style {
         if args.fallback_config {
             log::error!("Loading cthulock config failed. Loading fallback config. Errors: \n{e}");
-            load_style(FALLBACK_STYLE.to_owned(), vec![], true)
+            load_style(FALLBACK_STYLE.to_owned(), vec![], &[], true)
         } else {
             Err(e)
         }
@@ -45,47 +93,221 @@ fn get_style_and_include_paths() -> Result<(String, Vec<PathBuf>)> {
     Ok((style, config_dirs))
 }
 
-/// Load a slint style from a string with the include paths and check all the required properties and callbacks are present
+/// Load a slint style from a string with the include paths, together with the components for specific outputs.
+/// Checks all the required properties and callbacks are present
 fn load_style(
     style: String,
     include_paths: Vec<PathBuf>,
+    outputs: &[OutputConfig],
     supress_warnings: bool,
-) -> Result<ComponentDefinition> {
+) -> Result<Style> {
     let mut compiler = Compiler::default();
-    compiler.set_include_paths(include_paths);
+    compiler.set_include_paths(include_paths.clone());
 
     let result = block_on(compiler.build_from_source(style, Default::default()));
     result.print_diagnostics();
-    let definition = result.component(result.component_names().next().unwrap_or_default());
-    let definition = definition.ok_or(CthulockError::Generic(
-        "Compiling the Slint code failed".to_owned(),
-    ))?;
+    if result.has_errors() {
+        return Err(CthulockError::Generic(
+            "Compiling the Slint code failed".to_owned(),
+        ));
+    }
+
+    // components selected by name for specific outputs are not used as the default
+    let default_name = result
+        .component_names()
+        .find(|name| {
+            !outputs
+                .iter()
+                .any(|output| output.file.is_none() && output.component.as_deref() == Some(name))
+        })
+        .or_else(|| result.component_names().next())
+        .unwrap_or_default();
+    let default = result
+        .component(default_name)
+        .ok_or(CthulockError::Generic(
+            "Compiling the Slint code failed".to_owned(),
+        ))?;
+    let default = check_component(default, ScreenKind::Primary, supress_warnings)?;
+
+    let outputs = outputs
+        .iter()
+        .map(|output| {
+            let definition = match &output.file {
+                Some(file) => load_component_file(
+                    &compiler,
+                    &include_paths,
+                    file,
+                    output.component.as_deref(),
+                )?,
+                None => {
+                    let name = output.component.as_deref().unwrap_or_default();
+                    result
+                        .component(name)
+                        .ok_or(CthulockError::Generic(format!(
+                            "style.slint does not export a component named '{name}'"
+                        )))?
+                }
+            };
+            let kind = if output.secondary {
+                ScreenKind::Secondary
+            } else {
+                ScreenKind::Primary
+            };
+            Ok((
+                output.clone(),
+                check_component(definition, kind, supress_warnings)?,
+            ))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Style { default, outputs })
+}
+
+/// Compile a component from a file in the config directory. Uses the first exported component if no name is given
+fn load_component_file(
+    compiler: &Compiler,
+    include_paths: &[PathBuf],
+    file: &PathBuf,
+    component: Option<&str>,
+) -> Result<ComponentDefinition> {
+    let path = include_paths
+        .iter()
+        .rev()
+        .map(|dir| dir.join(file))
+        .find(|path| path.exists())
+        .ok_or(CthulockError::Generic(format!(
+            "Could not find {} in config paths",
+            file.display()
+        )))?;
+
+    let result = block_on(compiler.build_from_path(&path));
+    result.print_diagnostics();
+
+    let name = component.map_or_else(
+        || {
+            result
+                .component_names()
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        },
+        ToOwned::to_owned,
+    );
+    result
+        .component(&name)
+        .ok_or(CthulockError::Generic(format!(
+            "Compiling {} failed or it does not export a component named '{name}'",
+            path.display()
+        )))
+}
 
+/// Check the properties and callbacks of a component. Secondary screens only need to have the optional properties
+fn check_component(
+    definition: ComponentDefinition,
+    kind: ScreenKind,
+    supress_warnings: bool,
+) -> Result<StyleComponent> {
     let slint_properties: Vec<_> = definition.properties().map(SlintProperty::from).collect();
-    RequiredProperties::check_propreties(&slint_properties)?;
+    if kind == ScreenKind::Primary {
+        RequiredProperties::check_propreties(&slint_properties)?;
+    }
     if let Err(CthulockError::MissingProperties(properties)) =
         OptionalProperties::check_propreties(&slint_properties)
     {
         if !supress_warnings {
-            log::info!("The following optional properties are not set: {properties:?}");
+            log::info!(
+                "The following optional properties are not set on '{}': {properties:?}",
+                definition.name()
+            );
         }
     }
 
-    let slint_callbacks: Vec<_> = definition.callbacks().collect();
-    RequiredCallbacks::check_callbacks(&slint_callbacks)?;
+    if kind == ScreenKind::Primary {
+        let slint_callbacks: Vec<_> = definition.callbacks().collect();
+        RequiredCallbacks::check_callbacks(&slint_callbacks)?;
+        if let Err(CthulockError::MissingCallbacks(callbacks)) =
+            OptionalCallbacks::check_callbacks(&slint_callbacks)
+        {
+            if !supress_warnings {
+                log::info!(
+                    "The following optional callbacks are not set on '{}': {callbacks:?}",
+                    definition.name()
+                );
+            }
+        }
+    }
 
-    Ok(definition)
+    Ok(StyleComponent { definition, kind })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Result;
+    use crate::{
+        common::CthulockError,
+        config::{Config, OutputConfig},
+        message::OutputInfo,
+        Result,
+    };
+
+    use super::{load_style, ScreenKind, FALLBACK_STYLE};
 
-    use super::{load_style, FALLBACK_STYLE};
+    const MULTI_OUTPUT_STYLE: &str = r#"
+        export component Clock inherits Window {
+            in property<string> clock_text;
+        }
+        export component Main inherits Window {
+            in-out property<string> password;
+            callback submit(string);
+        }
+    "#;
+
+    fn output_config(config: &str) -> Vec<OutputConfig> {
+        toml::from_str::<Config>(config).unwrap().outputs
+    }
 
     #[test]
     fn test_fallback_config() -> Result<()> {
-        load_style(FALLBACK_STYLE.to_owned(), vec![], true)?;
+        load_style(FALLBACK_STYLE.to_owned(), vec![], &[], true)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_secondary_component() -> Result<()> {
+        let outputs = output_config(
+            r#"
+            [[output]]
+            name = "DP-2"
+            component = "Clock"
+            secondary = true
+            "#,
+        );
+        let style = load_style(MULTI_OUTPUT_STYLE.to_owned(), vec![], &outputs, true)?;
+        assert_eq!(style.default.definition.name(), "Main");
+
+        let side = OutputInfo {
+            name: Some("DP-2".to_owned()),
+            ..Default::default()
+        };
+        let component = style.component_for(&side);
+        assert_eq!(component.definition.name(), "Clock");
+        assert_eq!(component.kind, ScreenKind::Secondary);
+        assert_eq!(
+            style.component_for(&OutputInfo::default()).kind,
+            ScreenKind::Primary
+        );
         Ok(())
     }
+
+    #[test]
+    fn test_primary_component_needs_password() {
+        let outputs = output_config(
+            r#"
+            [[output]]
+            name = "DP-2"
+            component = "Clock"
+            "#,
+        );
+        let style = load_style(MULTI_OUTPUT_STYLE.to_owned(), vec![], &outputs, true);
+        assert!(matches!(style, Err(CthulockError::MissingProperties(_))));
+    }
 }
@@ -0,0 +1,148 @@
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+use slint::{Timer, TimerMode};
+use slint_interpreter::{SharedString, Value};
+
+use crate::{
+    config::ClockConfig,
+    ui::{locale::TimeLocale, slint_types::OptionalProperties},
+};
+
+/// Sets `clock_text` and the other time properties. A Slint timer fires whenever the shown minute or second changes
+pub struct Clock {
+    format: String,
+    date_format: String,
+    seconds: bool,
+    // `None` if the locale isn't installed, chrono's English names are used then
+    locale: Option<TimeLocale>,
+    timer: Timer,
+    // set by the timer, the properties are computed on the next call to `update`
+    due: Rc<Cell<bool>>,
+    // the minute or second since the epoch shown last
+    shown: Cell<Option<i64>>,
+}
+
+impl Clock {
+    /// `seconds` updates every second even if `clock_text` doesn't show them, for a theme using `seconds`
+    pub fn new(config: &ClockConfig, seconds: bool) -> Self {
+        Self {
+            format: config.format().to_owned(),
+            date_format: config.date_format().to_owned(),
+            seconds: seconds || config.shows_seconds(),
+            locale: TimeLocale::new(config.locale.as_deref()).or_else(|| {
+                log::warn!(
+                    "the locale {} is not installed, using English names",
+                    config.locale.as_deref().unwrap_or("selected by LC_TIME")
+                );
+                None
+            }),
+            timer: Timer::default(),
+            due: Rc::new(Cell::new(true)),
+            shown: Cell::new(None),
+        }
+    }
+
+    /// The new values of the time properties if the shown time changed since the last call
+    pub fn update(&self) -> Option<Vec<(OptionalProperties, Value)>> {
+        // Slint timers don't advance while the system is suspended, so after resuming the time shown
+        // is checked on every wake-up, not only when the timer fires
+        let time = Local::now();
+        let tick = if self.seconds {
+            time.timestamp()
+        } else {
+            time.timestamp().div_euclid(60)
+        };
+        if !self.due.replace(false) && self.shown.get() == Some(tick) {
+            return None;
+        }
+        self.shown.set(Some(tick));
+        let due = self.due.clone();
+        self.timer.start(
+            TimerMode::SingleShot,
+            self.until_next_tick(time),
+            move || due.set(true),
+        );
+        Some(self.properties(time))
+    }
+
+    fn properties(&self, time: DateTime<Local>) -> Vec<(OptionalProperties, Value)> {
+        let text = |format: &str| {
+            let text = match &self.locale {
+                Some(locale) => locale.format(&time, format),
+                None => time.format(format).to_string(),
+            };
+            Value::String(SharedString::from(text))
+        };
+        let number = |number: u32| Value::Number(number.into());
+        vec![
+            (OptionalProperties::ClockText, text(&self.format)),
+            (OptionalProperties::Hours, number(time.hour())),
+            (OptionalProperties::Minutes, number(time.minute())),
+            // a leap second is shown as 59
+            (OptionalProperties::Seconds, number(time.second().min(59))),
+            (OptionalProperties::Day, number(time.day())),
+            (OptionalProperties::Month, number(time.month())),
+            (OptionalProperties::Year, Value::Number(time.year().into())),
+            (OptionalProperties::WeekdayName, text("%A")),
+            (OptionalProperties::MonthName, text("%B")),
+            (OptionalProperties::DateText, text(&self.date_format)),
+        ]
+    }
+
+    /// Time until the minute or second shown by the clock changes
+    fn until_next_tick(&self, time: DateTime<Local>) -> Duration {
+        if self.seconds {
+            Duration::from_secs(1).saturating_sub(Duration::from_nanos(time.nanosecond().into()))
+        } else {
+            let elapsed = Duration::new(time.second().into(), time.nanosecond());
+            Duration::from_secs(60).saturating_sub(elapsed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+    use slint_interpreter::Value;
+
+    use super::Clock;
+    use crate::config::ClockConfig;
+
+    #[test]
+    fn test_time_properties() {
+        let clock = Clock::new(
+            &ClockConfig {
+                hour12: true,
+                locale: Some("C".to_owned()),
+                ..Default::default()
+            },
+            false,
+        );
+        let time = Local.with_ymd_and_hms(2024, 3, 9, 15, 4, 5).unwrap();
+        let properties: Vec<_> = clock
+            .properties(time)
+            .into_iter()
+            .map(|(property, value)| (property.to_string(), value))
+            .collect();
+
+        let expected = [
+            ("clock_text", Value::String("3:04 PM".into())),
+            ("hours", Value::Number(15.0)),
+            ("minutes", Value::Number(4.0)),
+            ("seconds", Value::Number(5.0)),
+            ("day", Value::Number(9.0)),
+            ("month", Value::Number(3.0)),
+            ("year", Value::Number(2024.0)),
+            ("weekday_name", Value::String("Saturday".into())),
+            ("month_name", Value::String("March".into())),
+            ("date_text", Value::String("Saturday, 03/09/24".into())),
+        ];
+        for (property, value) in expected {
+            assert!(
+                properties.contains(&(property.to_owned(), value)),
+                "wrong {property}"
+            );
+        }
+    }
+}
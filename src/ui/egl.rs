@@ -4,7 +4,7 @@ use glutin::{
     context::ContextAttributesBuilder,
     display::GetGlDisplay,
     prelude::*,
-    surface::{SurfaceAttributesBuilder, WindowSurface},
+    surface::{SurfaceAttributesBuilder, SwapInterval, WindowSurface},
 };
 use raw_window_handle::{
     RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle,
@@ -15,16 +15,23 @@ use std::{
     ffi::{c_void, CStr},
     num::NonZeroU32,
     ptr::NonNull,
+    rc::Rc,
 };
 use wayland_client::backend::ObjectId;
 
+use crate::{common::CthulockError, Result};
+
+/// Cloning shares the context, so a lock screen can resize the surface the renderer draws to
+#[derive(Clone)]
 pub struct OpenGLContext {
-    context: PossiblyCurrentContext,
-    surface: Surface<WindowSurface>,
+    context: Rc<PossiblyCurrentContext>,
+    surface: Rc<Surface<WindowSurface>>,
 }
 
 impl OpenGLContext {
-    pub fn new(display_id: ObjectId, surface_id: ObjectId, size: (u32, u32)) -> Self {
+    pub fn new(display_id: ObjectId, surface_id: ObjectId, size: (u32, u32)) -> Result<Self> {
+        let egl_error = |what: &str, e: &dyn Error| CthulockError::Generic(format!("{what}: {e}"));
+
         let handle = WaylandDisplayHandle::new(
             NonNull::<c_void>::new(display_id.as_ptr() as *mut _)
                 .expect("error converting to cvoid"),
@@ -33,11 +40,11 @@ impl OpenGLContext {
 
         let config_template = ConfigTemplateBuilder::new().with_alpha_size(8).build();
 
-        let glutin_display =
-            unsafe { Display::new(display_handle).expect("Failed to create EGL Display") };
+        let glutin_display = unsafe { Display::new(display_handle) }
+            .map_err(|e| egl_error("Failed to create EGL Display", &e))?;
 
         let config = unsafe { glutin_display.find_configs(config_template) }
-            .unwrap()
+            .map_err(|e| egl_error("Failed to query EGL configs", &e))?
             .reduce(|config, acc| {
                 if config.num_samples() > acc.num_samples() {
                     config
@@ -45,15 +52,12 @@ impl OpenGLContext {
                     acc
                 }
             })
-            .expect("No available configs");
+            .ok_or_else(|| CthulockError::Generic("No available EGL configs".to_owned()))?;
 
         let context_attributes = ContextAttributesBuilder::new().build(None);
 
-        let not_current = unsafe {
-            glutin_display
-                .create_context(&config, &context_attributes)
-                .expect("Failed to create OpenGL context")
-        };
+        let not_current = unsafe { glutin_display.create_context(&config, &context_attributes) }
+            .map_err(|e| egl_error("Failed to create OpenGL context", &e))?;
 
         let handle = WaylandWindowHandle::new(
             NonNull::<c_void>::new(surface_id.as_ptr() as *mut _)
@@ -69,22 +73,27 @@ impl OpenGLContext {
             NonZeroU32::new(height).unwrap(),
         );
 
-        let surface = unsafe {
-            glutin_display
-                .create_window_surface(&config, &attrs)
-                .expect("Failed to create OpenGl surface")
-        };
+        let surface = unsafe { glutin_display.create_window_surface(&config, &attrs) }
+            .map_err(|e| egl_error("Failed to create OpenGL surface", &e))?;
 
         let context = not_current
             .make_current(&surface)
-            .expect("Failed to make newly created OpenGL context current");
+            .map_err(|e| egl_error("Failed to make newly created OpenGL context current", &e))?;
+
+        // frames are paced with frame callbacks, so swapping buffers must not block on them
+        if let Err(e) = surface.set_swap_interval(&context, SwapInterval::DontWait) {
+            log::warn!("failed to disable vsync: {e}");
+        }
 
-        Self { context, surface }
+        Ok(Self {
+            context: Rc::new(context),
+            surface: Rc::new(surface),
+        })
     }
 }
 
 unsafe impl OpenGLInterface for OpenGLContext {
-    fn ensure_current(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    fn ensure_current(&self) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
         log::debug!("ensuring context is current");
         if !self.context.is_current() {
             log::info!("context not current. Making current");
@@ -93,7 +102,7 @@ unsafe impl OpenGLInterface for OpenGLContext {
         Ok(())
     }
 
-    fn swap_buffers(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    fn swap_buffers(&self) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
         log::debug!("swapping buffers");
         self.surface.swap_buffers(&self.context)?;
         Ok(())
@@ -103,7 +112,7 @@ unsafe impl OpenGLInterface for OpenGLContext {
         &self,
         width: NonZeroU32,
         height: NonZeroU32,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
         self.ensure_current()?;
         log::debug!("resizing surface to {width},{height}");
         self.surface.resize(&self.context, width, height);
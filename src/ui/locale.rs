@@ -0,0 +1,155 @@
+use std::ffi::{CStr, CString};
+
+use chrono::{
+    format::{Fixed, Item, StrftimeItems},
+    DateTime, Datelike, Local, Timelike,
+};
+
+/// Names and formats of an `LC_TIME` locale, read with nl_langinfo(3).
+/// chrono only knows English, so these replace its names and its expansions of `%c`, `%x`, `%X` and `%r`
+pub struct TimeLocale {
+    short_weekdays: Vec<String>,
+    weekdays: Vec<String>,
+    short_months: Vec<String>,
+    months: Vec<String>,
+    am_pm: [String; 2],
+    date_time_format: String,
+    date_format: String,
+    time_format: String,
+    time_format_ampm: String,
+}
+
+impl TimeLocale {
+    /// A locale like `de_DE.UTF-8`, or the one `LC_ALL`, `LC_TIME` and `LANG` select if `None`.
+    /// `None` if the locale isn't installed
+    pub fn new(name: Option<&str>) -> Option<Self> {
+        let name = CString::new(name.unwrap_or("")).ok()?;
+        let locale =
+            unsafe { libc::newlocale(libc::LC_TIME_MASK, name.as_ptr(), std::ptr::null_mut()) };
+        if locale.is_null() {
+            return None;
+        }
+
+        // the strings belong to the locale, so they are copied before it is freed
+        let item = |item: libc::nl_item| {
+            let value = unsafe { CStr::from_ptr(libc::nl_langinfo_l(item, locale)) };
+            String::from_utf8_lossy(value.to_bytes()).into_owned()
+        };
+        let items = |first: libc::nl_item, count: i32| (first..first + count).map(item).collect();
+        let codeset = item(libc::CODESET);
+        if codeset != "UTF-8" && codeset != "ANSI_X3.4-1968" {
+            log::warn!("the locale uses {codeset}, names may not be shown correctly. Use a UTF-8 locale instead");
+        }
+        let time_locale = Self {
+            short_weekdays: items(libc::ABDAY_1, 7),
+            weekdays: items(libc::DAY_1, 7),
+            short_months: items(libc::ABMON_1, 12),
+            months: items(libc::MON_1, 12),
+            am_pm: [item(libc::AM_STR), item(libc::PM_STR)],
+            date_time_format: item(libc::D_T_FMT),
+            date_format: item(libc::D_FMT),
+            time_format: item(libc::T_FMT),
+            time_format_ampm: item(libc::T_FMT_AMPM),
+        };
+        unsafe { libc::freelocale(locale) };
+        Some(time_locale)
+    }
+
+    /// Format `time` with a strftime format, like `DateTime::format`.
+    /// Never panics, specifiers chrono doesn't support are shown as they are
+    pub fn format(&self, time: &DateTime<Local>, format: &str) -> String {
+        let weekday = time.weekday().num_days_from_sunday() as usize;
+        let month = time.month0() as usize;
+        let am_pm = &self.am_pm[usize::from(time.hour12().0)];
+        let lower_am_pm = am_pm.to_lowercase();
+
+        // `%c` of most locales contains `%r` or `%X`, so it takes two rounds
+        let format = self.expand(&self.expand(format));
+        // formatting panics on errors, and the formats of some locales use specifiers like `%^a` chrono doesn't know
+        let items: Vec<_> = StrftimeItems::new_lenient(&format)
+            .map(|item| {
+                let name = match item {
+                    Item::Fixed(Fixed::ShortWeekdayName) => &self.short_weekdays[weekday],
+                    Item::Fixed(Fixed::LongWeekdayName) => &self.weekdays[weekday],
+                    Item::Fixed(Fixed::ShortMonthName) => &self.short_months[month],
+                    Item::Fixed(Fixed::LongMonthName) => &self.months[month],
+                    Item::Fixed(Fixed::UpperAmPm) => am_pm,
+                    Item::Fixed(Fixed::LowerAmPm) => &lower_am_pm,
+                    item => return item,
+                };
+                Item::OwnedLiteral(name.as_str().into())
+            })
+            .collect();
+        time.format_with_items(items.iter()).to_string()
+    }
+
+    /// Replace the specifiers chrono expands itself with the formats of the locale.
+    /// The `E` and `O` modifiers for alternative eras and digits are dropped, chrono doesn't support them
+    fn expand(&self, format: &str) -> String {
+        let mut expanded = String::with_capacity(format.len());
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            let mut next = chars.next();
+            if matches!(next, Some('E' | 'O')) {
+                next = chars.next();
+            }
+            match next {
+                Some('c') => expanded.push_str(&self.date_time_format),
+                Some('x') => expanded.push_str(&self.date_format),
+                Some('X') => expanded.push_str(&self.time_format),
+                Some('r') => expanded.push_str(&self.time_format_ampm),
+                Some(next) => {
+                    expanded.push('%');
+                    expanded.push(next);
+                }
+                None => expanded.push('%'),
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::TimeLocale;
+
+    #[test]
+    fn test_time_locale() {
+        let time = Local.with_ymd_and_hms(2024, 3, 9, 15, 4, 5).unwrap();
+
+        let locale = TimeLocale::new(Some("C")).unwrap();
+        assert_eq!(
+            locale.format(&time, "%a %A %b %B %p"),
+            "Sat Saturday Mar March PM"
+        );
+        assert_eq!(locale.format(&time, "%x %X %%x"), "03/09/24 15:04:05 %x");
+        assert!(TimeLocale::new(Some("xx_XX.UTF-8")).is_none());
+
+        // formats of th_TH and fa_IR
+        let mut locale = TimeLocale::new(Some("C")).unwrap();
+        locale.date_format = "%Od/%m/%Ey".to_owned();
+        locale.time_format = "%OH:%OM:%OS".to_owned();
+        assert_eq!(
+            locale.format(&time, "%A, %x %X"),
+            "Saturday, 09/03/24 15:04:05"
+        );
+        // unsupported by chrono
+        locale.date_format = "%^a %d".to_owned();
+        assert_eq!(locale.format(&time, "%x"), "%^a 09");
+    }
+
+    #[test]
+    #[ignore = "needs the de_DE.UTF-8 locale"]
+    fn test_german_time_locale() {
+        let time = Local.with_ymd_and_hms(2024, 3, 9, 15, 4, 5).unwrap();
+        let locale = TimeLocale::new(Some("de_DE.UTF-8")).expect("de_DE.UTF-8 is not installed");
+        assert_eq!(locale.format(&time, "%A, %x"), "Samstag, 09.03.2024");
+        assert_eq!(locale.format(&time, "%-d. %B"), "9. März");
+    }
+}
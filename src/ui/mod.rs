@@ -1,97 +1,344 @@
 use crate::{
     common::CthulockError,
-    message::{UiMessage, WindowingMessage},
+    config::ClockConfig,
+    message::{AuthRequest, OutputId, OutputInfo, UiMessage, WindowingMessage},
+    style::{ScreenKind, Style},
     ui::{
+        clock::Clock,
         egl::OpenGLContext,
-        platform::CthulockSlintPlatform,
+        platform::{CthulockSlintPlatform, NextWindow},
+        shm::ShmWindow,
         slint_types::{OptionalProperties, RequiredProperties},
-        window_adapter::MinimalFemtoVGWindow,
+        window_adapter::{LockWindow, MinimalFemtoVGWindow},
     },
+    windowing_thread::LockSurface,
     Result,
 };
-use chrono::Local;
-use slint::{platform::femtovg_renderer::FemtoVGRenderer, PhysicalSize};
+use slint::{
+    platform::{femtovg_renderer::FemtoVGRenderer, WindowEvent},
+    ModelRc, PhysicalSize, VecModel,
+};
 use slint_interpreter::{
     ComponentDefinition, ComponentHandle, ComponentInstance, SharedString, Value,
 };
 use std::{
-    rc::Rc,
-    sync::mpsc::{Receiver, Sender, TryRecvError},
-    time::Duration,
+    collections::HashMap,
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError},
+    time::{Duration, Instant},
 };
+use wayland_client::{backend::ObjectId, protocol::wl_shm, Proxy};
 
-use self::slint_types::RequiredCallbacks;
+use self::slint_types::{OptionalCallbacks, RequiredCallbacks};
 
+mod clock;
 mod egl;
+mod locale;
 mod platform;
+mod shm;
 pub(crate) mod slint_types;
 mod window_adapter;
 
+/// The Slint window and component shown on a single output
+struct LockScreen {
+    window: LockWindow,
+    ui: ComponentInstance,
+    kind: ScreenKind,
+    surface: LockSurface,
+    /// A frame was committed and the compositor hasn't asked for the next one yet
+    frame_pending: bool,
+
+    /// Size in surface-local coordinates
+    size: (u32, u32),
+    scale: f32,
+}
+
+/// State of the UI thread shared by all lock screens
+struct UiState {
+    style: Style,
+    sender: Sender<UiMessage>,
+    auth_sender: Sender<AuthRequest>,
+    next_window: NextWindow,
+    screens: HashMap<OutputId, LockScreen>,
+    /// Optional properties set on every screen, also applied to screens created later
+    shared_properties: HashMap<String, Value>,
+    /// Until then the authentication thread rejects attempts, counted down in `retry_after_seconds`
+    retry_deadline: Option<Instant>,
+    /// Until then any input unlocks the session, counted down in `grace_remaining`
+    grace_deadline: Option<Instant>,
+    /// Set by `--software-rendering` or once creating an OpenGL context failed
+    software_rendering: bool,
+}
+
 pub fn ui_thread(
-    style: ComponentDefinition,
+    style: Style,
+    software_rendering: bool,
+    clock: ClockConfig,
     sender: Sender<UiMessage>,
+    auth_sender: Sender<AuthRequest>,
     receiver: Receiver<WindowingMessage>,
 ) -> Result<()> {
-    let slint_window = wait_for_configure_and_set_platform(&receiver)?;
+    let next_window = NextWindow::default();
+    let platform = CthulockSlintPlatform::new(next_window.clone());
+    slint::platform::set_platform(Box::new(platform)).unwrap();
 
-    let ui = create_ui(sender.clone(), style)?;
-    ui.show().unwrap();
+    let mut state = UiState {
+        style,
+        sender,
+        auth_sender,
+        next_window,
+        screens: HashMap::new(),
+        shared_properties: HashMap::new(),
+        retry_deadline: None,
+        grace_deadline: None,
+        software_rendering,
+    };
+    let clock = Clock::new(
+        &clock,
+        state.style.uses_property(&OptionalProperties::Seconds),
+    );
 
     loop {
         slint::platform::update_timers_and_animations();
 
-        if receive_messages(&receiver, Rc::clone(&slint_window), &ui).is_err() {
-            return Ok(());
-        }
+        let countdown_timeout = update_countdowns(&mut state);
 
-        let time = Local::now();
-        let _ = ui.set_property(
-            &OptionalProperties::ClockText,
-            SharedString::from(time.format("%H:%M").to_string()).into(),
-        );
+        for (property, value) in clock.update().into_iter().flatten() {
+            set_shared_property(&mut state, property, value);
+        }
 
-        slint_window.draw_if_needed();
+        for screen in state.screens.values_mut() {
+            // running animations are advanced once the compositor asks for the next frame
+            if !screen.frame_pending && screen.window.draw_if_needed(&screen.surface) {
+                screen.frame_pending = true;
+            }
+        }
 
-        if !slint_window.has_active_animations() {
-            let duration = slint::platform::duration_until_next_timer_update()
-                .map_or(Duration::from_millis(8), |d| {
-                    d.min(Duration::from_millis(8))
-                });
-            std::thread::sleep(duration);
+        // sleep until the windowing thread sends something, a Slint timer like the clock's fires or a countdown changes
+        let timeout = slint::platform::duration_until_next_timer_update()
+            .unwrap_or(Duration::MAX)
+            .min(countdown_timeout.unwrap_or(Duration::MAX));
+        match receive_messages(&receiver, &mut state, timeout) {
+            Err(CthulockError::WindowingThreadQuit) => return Ok(()),
+            result => result?,
         }
     }
 }
 
-fn handle_message(
-    message: WindowingMessage,
-    slint_window: Rc<MinimalFemtoVGWindow>,
-    ui: &ComponentInstance,
-) -> Result<()> {
+/// Update `retry_after_seconds` and `grace_remaining`. Returns the time until one of them changes again
+fn update_countdowns(state: &mut UiState) -> Option<Duration> {
+    let retry = update_countdown(
+        state,
+        state.retry_deadline,
+        OptionalProperties::RetryAfterSeconds,
+    );
+    if retry.is_none() {
+        state.retry_deadline = None;
+    }
+    let grace = update_countdown(
+        state,
+        state.grace_deadline,
+        OptionalProperties::GraceRemaining,
+    );
+    if grace.is_none() {
+        state.grace_deadline = None;
+    }
+    retry.into_iter().chain(grace).min()
+}
+
+/// Set `property` to the whole seconds left until `deadline`. Returns the time until it changes, or `None` once it passed
+fn update_countdown(
+    state: &mut UiState,
+    deadline: Option<Instant>,
+    property: OptionalProperties,
+) -> Option<Duration> {
+    let remaining = deadline?.saturating_duration_since(Instant::now());
+    let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    set_shared_property(state, property, (seconds as f64).into());
+
+    if remaining.is_zero() {
+        return None;
+    }
+    Some(match remaining.subsec_nanos() {
+        0 => Duration::from_secs(1),
+        nanos => Duration::from_nanos(nanos.into()),
+    })
+}
+
+fn handle_message(message: WindowingMessage, state: &mut UiState) -> Result<()> {
     match message {
-        WindowingMessage::SlintWindowEvent(event) => slint_window.dispatch_event(event),
-        WindowingMessage::UnlockFailed => {
-            let _ = ui.set_property(&OptionalProperties::CheckingPassword, false.into());
-            let _ = ui.set_property(&RequiredProperties::Password, SharedString::from("").into());
+        WindowingMessage::SurfaceReady {
+            output,
+            info,
+            display_id,
+            surface,
+            shm,
+            size,
+            scale,
+            serial,
+        } => {
+            // the other outputs stay usable if one of them can't show a lock screen
+            let screen =
+                create_window(state, display_id, &surface, shm, physical_size(size, scale))
+                    .and_then(|window| {
+                        create_lock_screen(state, &info, window, *surface, size, scale)
+                    });
+            match screen {
+                Ok(screen) => {
+                    screen.surface.configure(Some(serial), size, scale);
+                    state.screens.insert(output, screen);
+                }
+                Err(e) => log::error!("failed to create the lock screen for output {info:?}: {e}"),
+            }
+        }
+        WindowingMessage::Resized {
+            output,
+            size,
+            serial,
+        } => {
+            if let Some(screen) = state.screens.get_mut(&output) {
+                screen.size = size;
+                resize_lock_screen(screen);
+                // every frame from now on has the new size
+                screen.surface.configure(Some(serial), size, screen.scale);
+            }
+        }
+        WindowingMessage::ScaleChanged { output, scale } => {
+            if let Some(screen) = state.screens.get_mut(&output) {
+                screen.scale = scale;
+                screen.window.set_scale_factor(scale);
+                resize_lock_screen(screen);
+                screen.surface.configure(None, screen.size, scale);
+            }
+        }
+        WindowingMessage::OutputRemoved { output } => {
+            if let Some(screen) = state.screens.remove(&output) {
+                let _ = screen.ui.hide();
+            }
+            state
+                .sender
+                .send(UiMessage::OutputReleased { output })
+                .unwrap();
+        }
+        WindowingMessage::GracePeriod { remaining } => {
+            state.grace_deadline = Some(Instant::now() + remaining);
+        }
+        WindowingMessage::FrameDone { output } => {
+            if let Some(screen) = state.screens.get_mut(&output) {
+                screen.frame_pending = false;
+            }
+        }
+        WindowingMessage::SlintWindowEvent { output, event } => {
+            let is_key_event = matches!(
+                event,
+                WindowEvent::KeyPressed { .. } | WindowEvent::KeyReleased { .. }
+            );
+            let screen = state.screens.get(&output).and_then(|screen| {
+                // secondary screens have no password field, so typing goes to a primary screen
+                if is_key_event && screen.kind == ScreenKind::Secondary {
+                    primary_screen(&state.screens)
+                } else {
+                    Some(screen)
+                }
+            });
+            if let Some(screen) = screen {
+                screen.window.dispatch_event(event);
+            }
+        }
+        WindowingMessage::KeyboardState {
+            caps_lock,
+            num_lock,
+            layout,
+        } => {
+            set_shared_property(state, OptionalProperties::CapsLock, caps_lock.into());
+            set_shared_property(state, OptionalProperties::NumLock, num_lock.into());
+            set_shared_property(
+                state,
+                OptionalProperties::KeyboardLayout,
+                SharedString::from(layout).into(),
+            );
+        }
+        WindowingMessage::KeyboardLayouts { layouts } => {
+            let layouts: Vec<Value> = layouts
+                .into_iter()
+                .map(|layout| Value::String(layout.into()))
+                .collect();
+            set_shared_property(
+                state,
+                OptionalProperties::KeyboardLayouts,
+                Value::Model(ModelRc::new(VecModel::from(layouts))),
+            );
+        }
+        WindowingMessage::AuthPrompt { prompt, secret } => {
+            set_shared_property(
+                state,
+                OptionalProperties::PamPrompt,
+                SharedString::from(prompt).into(),
+            );
+            set_shared_property(state, OptionalProperties::PromptIsSecret, secret.into());
+            reset_password(state);
+        }
+        WindowingMessage::AuthMessage { message } => {
+            set_shared_property(
+                state,
+                OptionalProperties::PamMessage,
+                SharedString::from(message).into(),
+            );
+        }
+        WindowingMessage::UnlockFailed {
+            reason,
+            message,
+            failed_attempts,
+            retry_after,
+        } => {
+            set_shared_property(
+                state,
+                OptionalProperties::ErrorText,
+                SharedString::from(message).into(),
+            );
+            set_shared_property(
+                state,
+                OptionalProperties::ErrorKind,
+                SharedString::from(reason.kind()).into(),
+            );
+            set_shared_property(
+                state,
+                OptionalProperties::FailedAttempts,
+                f64::from(failed_attempts).into(),
+            );
+            state.retry_deadline = Some(Instant::now() + retry_after);
+            set_shared_property(
+                state,
+                OptionalProperties::PamPrompt,
+                SharedString::new().into(),
+            );
+            set_shared_property(state, OptionalProperties::PromptIsSecret, true.into());
+            reset_password(state);
         }
         WindowingMessage::Quit => {
             log::info!("quitting UI thread...");
+            reset_password(state);
             return Err(CthulockError::WindowingThreadQuit);
         }
-        WindowingMessage::SurfaceReady { .. } => panic!("surface already configured"),
     }
     Ok(())
 }
 
+/// Wait up to `timeout` for a message, then handle all queued messages
 fn receive_messages(
     receiver: &Receiver<WindowingMessage>,
-    slint_window: Rc<MinimalFemtoVGWindow>,
-    ui: &ComponentInstance,
+    state: &mut UiState,
+    timeout: Duration,
 ) -> Result<()> {
+    match receiver.recv_timeout(timeout) {
+        Ok(message) => handle_message(message, state)?,
+        Err(RecvTimeoutError::Timeout) => return Ok(()),
+        Err(RecvTimeoutError::Disconnected) => return Err(CthulockError::WindowingThreadQuit),
+    }
     loop {
         let message = receiver.try_recv();
         match message {
             Ok(message) => {
-                handle_message(message, slint_window.clone(), ui)?;
+                handle_message(message, state)?;
             }
             Err(TryRecvError::Empty) => return Ok(()),
             Err(TryRecvError::Disconnected) => {
@@ -101,54 +348,182 @@ fn receive_messages(
     }
 }
 
-fn create_ui(sender: Sender<UiMessage>, style: ComponentDefinition) -> Result<ComponentInstance> {
+/// Clear the password field and let the user type again
+fn reset_password(state: &UiState) {
+    for screen in state.screens.values() {
+        let _ = screen
+            .ui
+            .set_property(&OptionalProperties::CheckingPassword, false.into());
+        let _ = screen
+            .ui
+            .set_property(&RequiredProperties::Password, SharedString::from("").into());
+    }
+}
+
+/// Set an optional property on all screens, including those created later
+fn set_shared_property(state: &mut UiState, property: OptionalProperties, value: Value) {
+    for screen in state.screens.values() {
+        let _ = screen.ui.set_property(&property, value.clone());
+    }
+    state.shared_properties.insert(property.to_string(), value);
+}
+
+/// Size of the buffer rendered for a surface of `size` at `scale`
+fn physical_size((width, height): (u32, u32), scale: f32) -> PhysicalSize {
+    PhysicalSize::new(
+        (width as f32 * scale).round() as u32,
+        (height as f32 * scale).round() as u32,
+    )
+}
+
+fn resize_lock_screen(screen: &LockScreen) {
+    screen
+        .window
+        .resize(physical_size(screen.size, screen.scale));
+}
+
+/// The primary screen with the lowest output id
+fn primary_screen(screens: &HashMap<OutputId, LockScreen>) -> Option<&LockScreen> {
+    screens
+        .iter()
+        .filter(|(_, screen)| screen.kind == ScreenKind::Primary)
+        .min_by_key(|(output, _)| **output)
+        .map(|(_, screen)| screen)
+}
+
+fn create_ui(
+    sender: Sender<AuthRequest>,
+    windowing_sender: Sender<UiMessage>,
+    style: &ComponentDefinition,
+) -> Result<ComponentInstance> {
     let ui = style.create().unwrap();
 
     let sender_clone = sender.clone();
     let ui_ref = ui.as_weak();
     ui.set_callback(&RequiredCallbacks::Submit, move |args: &[Value]| -> Value {
         let ui = ui_ref.upgrade().unwrap();
-        let Value::String(password) = args[0].clone() else {
+        let Value::String(password) = &args[0] else {
             panic!("Value in submit callback is not a String");
         };
 
+        // an empty or missing username means the user running cthulock
+        let username = match ui.get_property(&OptionalProperties::Username) {
+            Ok(Value::String(username)) if !username.is_empty() => Some(username.to_string()),
+            _ => None,
+        };
+
         let _ = ui.set_property(&OptionalProperties::CheckingPassword, true.into());
         sender_clone
-            .send(UiMessage::UnlockWithPassword {
-                password: password.to_string(),
+            .send(AuthRequest::Submit {
+                password: password.as_str().into(),
+                username,
             })
             .unwrap();
+        // the password only lives on in the locked memory of the request
+        let _ = ui.set_property(&RequiredProperties::Password, SharedString::new().into());
         Value::Void
     })
     .unwrap();
 
+    let _ = ui.set_callback(&OptionalCallbacks::SwitchLayout, move |args: &[Value]| {
+        let Some(Value::Number(layout)) = args.first() else {
+            panic!("Value in switch_layout callback is not a Number");
+        };
+        let _ = windowing_sender.send(UiMessage::SwitchLayout {
+            layout: *layout as u32,
+        });
+        Value::Void
+    });
+
+    let _ = ui.set_property(
+        &OptionalProperties::Username,
+        SharedString::from(whoami::username()).into(),
+    );
+
     Ok(ui)
 }
 
-fn wait_for_configure_and_set_platform(
-    receiver: &Receiver<WindowingMessage>,
-) -> Result<Rc<MinimalFemtoVGWindow>> {
-    let (display_id, surface_id, size) = match receiver.recv().unwrap() {
-        WindowingMessage::SurfaceReady {
+/// Create a window rendering with OpenGL, or in software if that is not possible
+fn create_window(
+    state: &mut UiState,
+    display_id: ObjectId,
+    surface: &LockSurface,
+    shm: wl_shm::WlShm,
+    size: PhysicalSize,
+) -> Result<LockWindow> {
+    if !state.software_rendering {
+        let window = OpenGLContext::new(
             display_id,
-            surface_id,
-            size,
-        } => (display_id, surface_id, size),
-        message => panic!(
-            "First message sent to render thread is not SurfaceReady. Is {:?}",
-            message
-        ),
-    };
+            surface.wl_surface().id(),
+            (size.width, size.height),
+        )
+        .and_then(|context| {
+            let renderer = FemtoVGRenderer::new(context.clone())
+                .map_err(|e| CthulockError::Generic(e.to_string()))?;
+            Ok(LockWindow::OpenGL {
+                context,
+                window: MinimalFemtoVGWindow::new(renderer),
+            })
+        });
+        match window {
+            Ok(window) => return Ok(window),
+            Err(e) => {
+                log::warn!("OpenGL is not available, falling back to software rendering: {e}");
+                state.software_rendering = true;
+            }
+        }
+    }
+    Ok(LockWindow::Software(Box::new(ShmWindow::new(
+        shm,
+        surface.wl_surface().clone(),
+        size,
+    )?)))
+}
 
-    let context = OpenGLContext::new(display_id, surface_id, size);
-    let renderer = FemtoVGRenderer::new(context).unwrap();
-    let slint_window = MinimalFemtoVGWindow::new(renderer);
-    slint_window.set_size(slint::WindowSize::Physical(PhysicalSize::new(
-        size.0, size.1,
-    )));
+/// Create the Slint component for a newly configured lock surface
+fn create_lock_screen(
+    state: &UiState,
+    info: &OutputInfo,
+    window: LockWindow,
+    surface: LockSurface,
+    size: (u32, u32),
+    scale: f32,
+) -> Result<LockScreen> {
+    window.set_scale_factor(scale);
+    window
+        .adapter()
+        .set_size(slint::WindowSize::Physical(physical_size(size, scale)));
 
-    let platform = CthulockSlintPlatform::new(slint_window.clone());
-    slint::platform::set_platform(Box::new(platform)).unwrap();
+    let component = state.style.component_for(info);
+    log::debug!(
+        "showing component '{}' on output {info:?}",
+        component.definition.name()
+    );
 
-    Ok(slint_window)
+    state.next_window.replace(Some(window.adapter()));
+    let ui = match component.kind {
+        ScreenKind::Primary => create_ui(
+            state.auth_sender.clone(),
+            state.sender.clone(),
+            &component.definition,
+        )?,
+        ScreenKind::Secondary => component
+            .definition
+            .create()
+            .map_err(|e| CthulockError::Generic(e.to_string()))?,
+    };
+    for (property, value) in &state.shared_properties {
+        let _ = ui.set_property(property, value.clone());
+    }
+    ui.show().unwrap();
+
+    Ok(LockScreen {
+        window,
+        ui,
+        kind: component.kind,
+        surface,
+        frame_pending: false,
+        size,
+        scale,
+    })
 }
@@ -1,23 +1,26 @@
-use crate::ui::window_adapter::MinimalFemtoVGWindow;
 use slint::{
     platform::{Platform, WindowAdapter},
     PlatformError,
 };
 use std::{
+    cell::RefCell,
     rc::Rc,
     time::{Duration, Instant},
 };
 
+/// The window handed out to the next component that gets created. Every output gets its own window
+pub type NextWindow = Rc<RefCell<Option<Rc<dyn WindowAdapter>>>>;
+
 pub struct CthulockSlintPlatform {
-    window: Rc<MinimalFemtoVGWindow>,
+    next_window: NextWindow,
 
     start_time: Instant,
 }
 
 impl CthulockSlintPlatform {
-    pub fn new(window: Rc<MinimalFemtoVGWindow>) -> Self {
+    pub fn new(next_window: NextWindow) -> Self {
         Self {
-            window,
+            next_window,
             start_time: Instant::now(),
         }
     }
@@ -25,7 +28,10 @@ impl CthulockSlintPlatform {
 
 impl Platform for CthulockSlintPlatform {
     fn create_window_adapter(&self) -> Result<Rc<dyn WindowAdapter>, PlatformError> {
-        Ok(self.window.clone())
+        self.next_window
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| PlatformError::Other("No window prepared for component".to_owned()))
     }
 
     fn duration_since_start(&self) -> Duration {
@@ -0,0 +1,132 @@
+use slint::{
+    platform::software_renderer::{
+        MinimalSoftwareWindow, PremultipliedRgbaColor, RepaintBufferType,
+    },
+    PhysicalSize,
+};
+use smithay_client_toolkit::{
+    error::GlobalError,
+    globals::ProvidesBoundGlobal,
+    shm::slot::{Buffer, SlotPool},
+};
+use std::{cell::RefCell, rc::Rc};
+use wayland_client::{
+    protocol::{wl_shm, wl_surface},
+    Proxy,
+};
+
+use crate::{common::CthulockError, Result};
+
+/// The `wl_shm` global bound by the windowing thread. Buffer events are dispatched on its queue
+struct BoundShm(wl_shm::WlShm);
+
+impl ProvidesBoundGlobal<wl_shm::WlShm, 1> for BoundShm {
+    fn bound_global(&self) -> std::result::Result<wl_shm::WlShm, GlobalError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Renders a Slint window on the CPU into `wl_shm` buffers. Used when OpenGL is not available
+pub struct ShmWindow {
+    window: Rc<MinimalSoftwareWindow>,
+    surface: wl_surface::WlSurface,
+    pool: RefCell<SlotPool>,
+    buffer: RefCell<Option<Buffer>>,
+    // the software renderer only repaints what changed, so the pixels are kept between frames
+    pixels: RefCell<Vec<PremultipliedRgbaColor>>,
+}
+
+impl ShmWindow {
+    pub fn new(
+        shm: wl_shm::WlShm,
+        surface: wl_surface::WlSurface,
+        size: PhysicalSize,
+    ) -> Result<Self> {
+        let pool = SlotPool::new((size.width * size.height * 4) as usize, &BoundShm(shm))
+            .map_err(|e| CthulockError::Generic(format!("Failed to create shm pool: {e}")))?;
+
+        Ok(Self {
+            window: MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer),
+            surface,
+            pool: RefCell::new(pool),
+            buffer: RefCell::new(None),
+            pixels: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn window(&self) -> &Rc<MinimalSoftwareWindow> {
+        &self.window
+    }
+
+    /// Renders a frame if the window changed. `before_commit` is called right before the buffer is committed
+    pub fn draw_if_needed(&self, before_commit: impl FnOnce()) -> bool {
+        self.window.draw_if_needed(|renderer| {
+            log::debug!("drawing new frame");
+
+            let size = self.window.size();
+            let (width, height) = (size.width as usize, size.height as usize);
+            let mut pixels = self.pixels.borrow_mut();
+            if pixels.len() != width * height {
+                *pixels = vec![PremultipliedRgbaColor::default(); width * height];
+                // the old contents are useless, everything has to be repainted
+                renderer.set_repaint_buffer_type(RepaintBufferType::NewBuffer);
+                renderer.render(&mut pixels, width);
+                renderer.set_repaint_buffer_type(RepaintBufferType::ReusedBuffer);
+            } else {
+                renderer.render(&mut pixels, width);
+            }
+
+            if let Err(e) = self.present(&pixels, size, before_commit) {
+                log::error!("failed to present frame: {e}");
+            }
+        })
+    }
+
+    /// Copy the rendered pixels into a free buffer and commit it
+    fn present(
+        &self,
+        pixels: &[PremultipliedRgbaColor],
+        size: PhysicalSize,
+        before_commit: impl FnOnce(),
+    ) -> Result<()> {
+        let (width, height) = (size.width as i32, size.height as i32);
+        let stride = width * 4;
+        let mut pool = self.pool.borrow_mut();
+        let mut buffer = self.buffer.borrow_mut();
+
+        // a buffer can only be reused once the compositor released it
+        let reusable = buffer.as_ref().is_some_and(|buffer| {
+            buffer.height() == height
+                && buffer.stride() == stride
+                && buffer.canvas(&mut pool).is_some()
+        });
+        if !reusable {
+            let (new_buffer, _) = pool
+                .create_buffer(width, height, stride, wl_shm::Format::Argb8888)
+                .map_err(|e| CthulockError::Generic(format!("Failed to create shm buffer: {e}")))?;
+            *buffer = Some(new_buffer);
+        }
+        let buffer = buffer.as_ref().unwrap();
+        let canvas = buffer.canvas(&mut pool).unwrap();
+
+        // Argb8888 is stored little endian
+        for (pixel, bytes) in pixels.iter().zip(canvas.chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&[pixel.blue, pixel.green, pixel.red, pixel.alpha]);
+        }
+
+        buffer
+            .attach_to(&self.surface)
+            .map_err(|e| CthulockError::Generic(format!("Failed to attach shm buffer: {e}")))?;
+        self.surface.damage(0, 0, i32::MAX, i32::MAX);
+        before_commit();
+        self.surface.commit();
+
+        // the windowing thread might be blocked waiting for events, so the commit has to be flushed here
+        if let Some(backend) = self.surface.backend().upgrade() {
+            backend
+                .flush()
+                .map_err(|e| CthulockError::Generic(format!("Failed to flush: {e}")))?;
+        }
+        Ok(())
+    }
+}
@@ -96,7 +96,29 @@ properties_check!(
 properties_check!(
     OptionalProperties,
     ClockText -> ("clock_text", ValueType::String),
-    CheckingPassword -> ("checking_password", ValueType::Bool)
+    Hours -> ("hours", ValueType::Number),
+    Minutes -> ("minutes", ValueType::Number),
+    Seconds -> ("seconds", ValueType::Number),
+    Day -> ("day", ValueType::Number),
+    Month -> ("month", ValueType::Number),
+    Year -> ("year", ValueType::Number),
+    WeekdayName -> ("weekday_name", ValueType::String),
+    MonthName -> ("month_name", ValueType::String),
+    DateText -> ("date_text", ValueType::String),
+    CheckingPassword -> ("checking_password", ValueType::Bool),
+    PamPrompt -> ("pam_prompt", ValueType::String),
+    PamMessage -> ("pam_message", ValueType::String),
+    PromptIsSecret -> ("prompt_is_secret", ValueType::Bool),
+    FailedAttempts -> ("failed_attempts", ValueType::Number),
+    RetryAfterSeconds -> ("retry_after_seconds", ValueType::Number),
+    GraceRemaining -> ("grace_remaining", ValueType::Number),
+    Username -> ("username", ValueType::String),
+    ErrorText -> ("error_text", ValueType::String),
+    ErrorKind -> ("error_kind", ValueType::String),
+    CapsLock -> ("caps_lock", ValueType::Bool),
+    NumLock -> ("num_lock", ValueType::Bool),
+    KeyboardLayout -> ("keyboard_layout", ValueType::String),
+    KeyboardLayouts -> ("keyboard_layouts", ValueType::Model)
 );
 
 macro_rules! callbacks_check {
@@ -144,3 +166,8 @@ callbacks_check!(
     RequiredCallbacks,
     Submit -> "submit"
 );
+
+callbacks_check!(
+    OptionalCallbacks,
+    SwitchLayout -> "switch_layout"
+);
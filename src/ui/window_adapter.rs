@@ -1,15 +1,25 @@
 use slint::{
-    platform::{femtovg_renderer::FemtoVGRenderer, Renderer, WindowAdapter, WindowEvent},
+    platform::{
+        femtovg_renderer::{FemtoVGRenderer, OpenGLInterface},
+        Renderer, WindowAdapter, WindowEvent,
+    },
     PhysicalSize, Window, WindowSize,
 };
 use std::cell::Cell;
+use std::num::NonZeroU32;
 use std::rc::{Rc, Weak};
 
+use crate::{
+    ui::{egl::OpenGLContext, shm::ShmWindow},
+    windowing_thread::LockSurface,
+};
+
 pub struct MinimalFemtoVGWindow {
     window: Window,
     renderer: FemtoVGRenderer,
     needs_redraw: Cell<bool>,
     size: Cell<PhysicalSize>,
+    scale_factor: Cell<f32>,
 }
 
 impl MinimalFemtoVGWindow {
@@ -19,15 +29,28 @@ impl MinimalFemtoVGWindow {
             renderer,
             needs_redraw: Default::default(),
             size: Default::default(),
+            scale_factor: Cell::new(1.),
         })
     }
 
-    pub fn draw_if_needed(&self) {
+    pub fn set_scale_factor(&self, scale_factor: f32) {
+        self.scale_factor.set(scale_factor);
+        self.window
+            .dispatch_event(WindowEvent::ScaleFactorChanged { scale_factor });
+        self.request_redraw();
+    }
+
+    /// Renders a frame if the window changed. `before_render` is called first, the frame is committed when rendering finishes
+    pub fn draw_if_needed(&self, before_render: impl FnOnce()) -> bool {
         if self.needs_redraw.get() {
             log::debug!("drawing new frame");
 
+            before_render();
             self.renderer.render().unwrap();
             self.needs_redraw.set(false);
+            true
+        } else {
+            false
         }
     }
 }
@@ -46,10 +69,12 @@ impl WindowAdapter for MinimalFemtoVGWindow {
     }
 
     fn set_size(&self, size: WindowSize) {
-        self.size.set(size.to_physical(1.));
+        let scale_factor = self.scale_factor.get();
+        self.size.set(size.to_physical(scale_factor));
         self.window.dispatch_event(WindowEvent::Resized {
-            size: size.to_logical(1.),
-        })
+            size: size.to_logical(scale_factor),
+        });
+        self.request_redraw();
     }
 
     fn request_redraw(&self) {
@@ -63,3 +88,67 @@ impl core::ops::Deref for MinimalFemtoVGWindow {
         &self.window
     }
 }
+
+/// The window a lock screen renders to, either with OpenGL or on the CPU into shared memory
+pub enum LockWindow {
+    OpenGL {
+        context: OpenGLContext,
+        window: Rc<MinimalFemtoVGWindow>,
+    },
+    Software(Box<ShmWindow>),
+}
+
+impl LockWindow {
+    pub fn adapter(&self) -> Rc<dyn WindowAdapter> {
+        match self {
+            LockWindow::OpenGL { window, .. } => window.clone(),
+            LockWindow::Software(shm) => shm.window().clone(),
+        }
+    }
+
+    pub fn set_scale_factor(&self, scale_factor: f32) {
+        match self {
+            LockWindow::OpenGL { window, .. } => window.set_scale_factor(scale_factor),
+            LockWindow::Software(shm) => {
+                // MinimalSoftwareWindow takes the scale factor from the window itself
+                shm.window()
+                    .dispatch_event(WindowEvent::ScaleFactorChanged { scale_factor });
+                shm.window().request_redraw();
+            }
+        }
+    }
+
+    pub fn resize(&self, size: PhysicalSize) {
+        if let LockWindow::OpenGL { context, .. } = self {
+            let (Some(width), Some(height)) =
+                (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+            else {
+                log::warn!("ignoring resize to {}x{}", size.width, size.height);
+                return;
+            };
+            if let Err(e) = context.resize(width, height) {
+                log::error!("failed to resize OpenGL surface: {e}");
+                return;
+            }
+        }
+        self.adapter().set_size(WindowSize::Physical(size));
+    }
+
+    /// Renders and commits a frame if the window changed, requesting a frame callback for it
+    pub fn draw_if_needed(&self, surface: &LockSurface) -> bool {
+        match self {
+            LockWindow::OpenGL { window, .. } => window.draw_if_needed(|| surface.request_frame()),
+            LockWindow::Software(shm) => shm.draw_if_needed(|| surface.request_frame()),
+        }
+    }
+}
+
+impl core::ops::Deref for LockWindow {
+    type Target = Window;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            LockWindow::OpenGL { window, .. } => window,
+            LockWindow::Software(shm) => shm.window(),
+        }
+    }
+}
@@ -1,127 +1,406 @@
 use crate::{
+    auth::{auth_thread, parallel_auth_thread, Authenticator},
     common::CthulockError,
-    message::{UiMessage, WindowingMessage},
+    config::Config,
+    hooks::{HookEvent, Hooks},
+    logind::{Logind, LogindEvent},
+    message::{AuthRequest, OutputId, OutputInfo, UiMessage, WindowingMessage},
+    ready::ReadyNotifier,
     Result,
 };
-use pam_client::{conv_mock::Conversation, Context, Flag};
 use slint::{
     platform::{Key, PointerEventButton, WindowEvent},
     LogicalPosition, SharedString,
 };
 use smithay_client_toolkit::{
-    delegate_keyboard, delegate_pointer, delegate_registry, delegate_seat, delegate_touch,
-    registry::{ProvidesRegistryState, RegistryState},
+    delegate_pointer, delegate_registry, delegate_seat, delegate_shm, delegate_touch,
+    registry::{ProvidesRegistryState, RegistryHandler, RegistryState},
     registry_handlers,
     seat::{
-        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
+        keyboard::{KeyEvent, KeyboardData, KeyboardHandler, Keymap, Keysym, Modifiers},
         pointer::{PointerEvent, PointerEventKind, PointerHandler},
         touch::TouchHandler,
         Capability, SeatHandler, SeatState,
     },
+    shm::{Shm, ShmHandler},
 };
 use std::{
     collections::HashMap,
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 use wayland_client::{
     delegate_noop,
-    globals::registry_queue_init,
+    globals::{registry_queue_init, GlobalList},
     protocol::{
-        wl_buffer, wl_compositor, wl_display, wl_keyboard, wl_output, wl_pointer, wl_seat,
-        wl_surface, wl_touch,
+        wl_buffer, wl_callback, wl_compositor, wl_display, wl_keyboard, wl_output, wl_pointer,
+        wl_seat, wl_surface, wl_touch,
     },
     Connection, Dispatch, Proxy, QueueHandle,
 };
-use wayland_protocols::ext::session_lock::v1::client::{
-    ext_session_lock_manager_v1, ext_session_lock_surface_v1, ext_session_lock_v1,
+use wayland_protocols::{
+    ext::session_lock::v1::client::{
+        ext_session_lock_manager_v1, ext_session_lock_surface_v1, ext_session_lock_v1,
+    },
+    wp::{
+        fractional_scale::v1::client::{wp_fractional_scale_manager_v1, wp_fractional_scale_v1},
+        viewporter::client::{wp_viewport, wp_viewporter},
+    },
 };
+use xkbcommon::xkb;
 
 pub fn windowing_thread(
     sender: Sender<WindowingMessage>,
     receiver: Receiver<UiMessage>,
+    auth_requests: Receiver<AuthRequest>,
+    config: Config,
+    authenticator: Box<dyn Authenticator>,
+    ready: ReadyNotifier,
 ) -> Result<()> {
     let conn = Connection::connect_to_env()
         .map_err(|_| CthulockError::Generic("Failed to connect to wayland.".to_owned()))?;
 
     let display = conn.display();
 
-    let mut event_queue = conn.new_event_queue();
+    let (globals, mut event_queue) = registry_queue_init::<AppData>(&conn).unwrap();
     let qh = event_queue.handle();
 
-    let (globals, _queue) = registry_queue_init::<AppData>(&conn).unwrap();
-
-    let compositor: wl_compositor::WlCompositor = globals.bind(&qh, 1..=5, ()).unwrap();
-    let wl_surface = compositor.create_surface(&qh, ());
-    let output: wl_output::WlOutput = globals.bind(&qh, 1..=1, ()).unwrap();
+    let compositor: wl_compositor::WlCompositor = globals.bind(&qh, 1..=6, ()).unwrap();
+    let shm = Shm::bind(&globals, &qh)
+        .map_err(|e| CthulockError::Generic(format!("Could not bind wl_shm: {e}")))?;
     let session_lock_manager: ext_session_lock_manager_v1::ExtSessionLockManagerV1 = globals.bind(&qh, 1..=1, ()).map_err(|_| {
         CthulockError::Generic("Could not bind ext-session-lock-v1. Your compositor probably does not support this.".to_owned())
     })?;
-    let session_lock = session_lock_manager.lock(&qh, ());
-    // set surface role as session lock surface
-    session_lock.get_lock_surface(&wl_surface, &output, &qh, ());
 
     let mut state = AppData::new(
-        RegistryState::new(&globals),
+        &globals,
+        &qh,
         display,
-        wl_surface,
-        session_lock,
-        SeatState::new(&globals, &qh),
+        compositor,
+        shm,
+        session_lock_manager,
         sender,
+        Hooks::new(config.hooks),
+        config.lock.grace_period(),
     );
 
+    for global in globals.contents().clone_list() {
+        if global.interface == wl_output::WlOutput::interface().name {
+            state.add_output(&qh, global.name);
+        }
+    }
+
+    if state.outputs.is_empty() {
+        return Err(CthulockError::Generic(
+            "The compositor did not advertise any outputs to lock.".to_owned(),
+        ));
+    }
+
+    let waker = Waker {
+        conn: conn.clone(),
+        qh: qh.clone(),
+    };
+    // the authentication threads report here when the session may be unlocked
+    let (unlock_sender, unlock_requests) = mpsc::channel::<()>();
+    let ui_sender = state.render_thread_sender.clone();
+    let (result_sender, result_waker) = (unlock_sender.clone(), waker.clone());
+    let auth_config = config.auth.clone();
+    let hooks = state.hooks.clone();
+    // PAM can take seconds and wait for further answers, so it must not block Wayland dispatch
+    std::thread::spawn(move || {
+        auth_thread(
+            auth_requests,
+            ui_sender,
+            auth_config,
+            authenticator,
+            || {
+                let _ = result_sender.send(());
+                result_waker.wake();
+            },
+            |failure, failed_attempts| {
+                hooks.run(
+                    HookEvent::AuthFailure,
+                    &[
+                        ("CTHULOCK_FAILED_ATTEMPTS", failed_attempts.to_string()),
+                        ("CTHULOCK_FAILURE_REASON", failure.reason.kind().to_owned()),
+                    ],
+                );
+            },
+        )
+    });
+    let mut parallel_service = config.auth.parallel_service;
+    let cancel_parallel = Arc::new(AtomicBool::new(false));
+
+    let (logind_sender, logind_events) = mpsc::channel::<LogindEvent>();
+    let mut logind = if config.lock.logind {
+        connect_logind(logind_sender, waker.clone())
+    } else {
+        None
+    };
+    let mut logind_unlock = false;
+    let mut password_unlock = false;
+
+    let mut attempt = 0;
+    let mut ready = Some(ready);
     while state.running {
         event_queue.blocking_dispatch(&mut state).unwrap();
 
+        if state.locked {
+            // the parallel transaction starts once the session is actually locked
+            if let Some(service) = parallel_service.take() {
+                let ui_sender = state.render_thread_sender.clone();
+                let cancelled = cancel_parallel.clone();
+                let (result_sender, waker) = (unlock_sender.clone(), waker.clone());
+                std::thread::spawn(move || {
+                    parallel_auth_thread(service, ui_sender, cancelled, || {
+                        let _ = result_sender.send(());
+                        waker.wake();
+                    })
+                });
+            }
+        }
+
+        if state.locked && state.outputs.values().all(|output| output.presented) {
+            if let Some(ready) = ready.take() {
+                ready.notify();
+                if let Some(logind) = &mut logind {
+                    logind.set_locked_hint(true);
+                    logind.release_sleep();
+                }
+            }
+        }
+
+        if state.lock_finished {
+            if state.locked {
+                log::error!("the compositor revoked the session lock, the session is unlocked now");
+                return Err(CthulockError::LockRevoked);
+            }
+            if attempt >= config.lock.retries {
+                log::error!("the compositor refused to lock the session");
+                let hook = state.hooks.run(
+                    HookEvent::LockDenied,
+                    &[("CTHULOCK_LOCK_ATTEMPTS", (attempt + 1).to_string())],
+                );
+                if let Some(hook) = hook {
+                    let _ = hook.join();
+                }
+                return Err(CthulockError::LockDenied);
+            }
+            let delay = config.lock.retry_delay(attempt);
+            attempt += 1;
+            log::warn!(
+                "the compositor refused to lock the session, retrying in {delay:?} ({attempt}/{})",
+                config.lock.retries
+            );
+            std::thread::sleep(delay);
+            state.relock(&qh);
+        }
+
         while let Ok(message) = receiver.try_recv() {
             match message {
-                UiMessage::UnlockWithPassword { password } => {
-                    let mut context = Context::new(
-                        "cthulock",
-                        None,
-                        Conversation::with_credentials(whoami::username(), password),
-                    )
-                    .expect("Failed to initialize PAM context");
-
-                    if context.authenticate(Flag::NONE).is_ok()
-                        && context.acct_mgmt(Flag::NONE).is_ok()
-                    {
-                        log::info!("authentication successfull, quitting...");
-                        state
-                            .render_thread_sender
-                            .send(WindowingMessage::Quit)
-                            .unwrap();
-                        state.session_lock.unlock_and_destroy();
-                        event_queue.roundtrip(&mut state).unwrap();
-                        state.running = false;
+                UiMessage::OutputReleased { output } => {
+                    if let Some(surface) = state.retired_surfaces.remove(&output) {
+                        log::debug!("destroying surface of removed output {output}");
+                        surface.destroy();
+                    }
+                }
+                UiMessage::SwitchLayout { layout } => {
+                    if (layout as usize) < state.layouts.len() {
+                        log::debug!("switching to layout {layout}");
+                        state.selected_layout = Some(layout);
+                        state.send_keyboard_state();
                     } else {
-                        state
-                            .render_thread_sender
-                            .send(WindowingMessage::UnlockFailed)
-                            .unwrap();
+                        log::warn!("switch_layout: there is no layout {layout}");
                     }
                 }
             }
         }
+
+        while let Ok(event) = logind_events.try_recv() {
+            match event {
+                LogindEvent::Unlock => logind_unlock = true,
+                LogindEvent::Lock => {
+                    log::debug!("the session is already locked, ending the grace period");
+                    state.end_grace_period();
+                }
+                // the inhibitor is held until the lock screen was shown
+                LogindEvent::PrepareForSleep(_) => {}
+            }
+        }
+
+        if unlock_requests.try_recv().is_ok() {
+            password_unlock = true;
+        }
+        // the lock can only be released once the compositor confirmed it, until then unlocking stays pending
+        if state.locked && (password_unlock || logind_unlock || state.grace.unlock) {
+            if logind_unlock {
+                log::info!("unlocked through logind, quitting...");
+            } else if state.grace.unlock {
+                log::info!("input during the grace period, quitting...");
+            } else {
+                log::info!("authentication successfull, quitting...");
+            }
+            cancel_parallel.store(true, Ordering::Relaxed);
+            state
+                .render_thread_sender
+                .send(WindowingMessage::Quit)
+                .unwrap();
+            state.session_lock.unlock_and_destroy();
+            event_queue.roundtrip(&mut state).unwrap();
+            state.running = false;
+            if let Some(logind) = &logind {
+                logind.set_locked_hint(false);
+            }
+
+            // cthulock exits right after, so the hook is waited for
+            let hook = state.hooks.run(
+                HookEvent::Unlock,
+                &[("CTHULOCK_OUTPUTS", state.outputs.len().to_string())],
+            );
+            if let Some(hook) = hook {
+                let _ = hook.join();
+            }
+        }
     }
     Ok(())
 }
 
-// TODO: Support multiple outputs
+/// Connect to logind and forward its signals to `events`. Without logind cthulock works as before
+fn connect_logind(events: Sender<LogindEvent>, waker: Waker) -> Option<Logind> {
+    let mut logind = match Logind::connect() {
+        Ok(logind) => logind,
+        Err(e) => {
+            log::warn!("not using logind: {e}");
+            return None;
+        }
+    };
+    if let Err(e) = logind.inhibit_sleep() {
+        log::warn!("failed to delay suspending until the session is locked: {e}");
+    }
+    let result = logind.listen(move |event| {
+        let _ = events.send(event);
+        waker.wake();
+    });
+    if let Err(e) = result {
+        log::warn!("failed to listen to logind signals: {e}");
+    }
+    Some(logind)
+}
+
+/// Wakes the windowing thread from `blocking_dispatch` by making the compositor send an event
+#[derive(Clone)]
+struct Waker {
+    conn: Connection,
+    qh: QueueHandle<AppData>,
+}
+
+impl Waker {
+    fn wake(&self) {
+        self.conn.display().sync(&self.qh, ());
+        let _ = self.conn.flush();
+    }
+}
+
+/// The `wl_surface` of a lock surface as handed to the UI thread.
+/// Frame callbacks requested through it are dispatched here and forwarded as `WindowingMessage::FrameDone`
+#[derive(Debug)]
+pub struct LockSurface {
+    surface: wl_surface::WlSurface,
+    lock_surface: ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+    viewport: Option<wp_viewport::WpViewport>,
+    qh: QueueHandle<AppData>,
+    output: OutputId,
+}
+
+impl LockSurface {
+    pub fn wl_surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Has to be called before the commit the frame callback belongs to
+    pub fn request_frame(&self) {
+        self.surface.frame(&self.qh, self.output);
+    }
+
+    /// Ack the configure `serial` and tell the compositor how buffers of `size` at `scale` map onto the surface.
+    /// The compositor disconnects if a buffer doesn't match the acked size, so this is only called
+    /// once the UI thread renders at the new size, before its next commit.
+    /// With a viewport the buffer is scaled to the surface size, so its size doesn't have to be an exact multiple
+    pub fn configure(&self, serial: Option<u32>, size: (u32, u32), scale: f32) {
+        if let Some(serial) = serial {
+            self.lock_surface.ack_configure(serial);
+        }
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(size.0 as i32, size.1 as i32),
+            None => self.surface.set_buffer_scale(scale.round() as i32),
+        }
+    }
+}
+
+/// A single output covered by a lock surface
+struct LockedOutput {
+    output: wl_output::WlOutput,
+    wl_surface: wl_surface::WlSurface,
+    lock_surface: ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+    viewport: Option<wp_viewport::WpViewport>,
+    fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+    info: OutputInfo,
+    configured: bool,
+    // the compositor sent the frame callback of the first frame
+    presented: bool,
+
+    // scale factors in order of preference. The fractional scale is in 120ths
+    preferred_fractional_scale: Option<u32>,
+    preferred_buffer_scale: Option<i32>,
+    output_scale: i32,
+    // the scale factor the UI thread currently renders with
+    sent_scale: f32,
+}
+
+impl LockedOutput {
+    fn scale_factor(&self) -> f32 {
+        if let Some(scale) = self.preferred_fractional_scale {
+            return scale as f32 / 120.;
+        }
+        self.preferred_buffer_scale.unwrap_or(self.output_scale) as f32
+    }
+}
+
 // This struct represents the state of our app
 struct AppData {
     running: bool,
     locked: bool,
-    configured: bool,
-    touches: HashMap<i32, LogicalPosition>,
+    // the compositor ended the lock, either refusing it or after it was locked
+    lock_finished: bool,
+    touches: HashMap<i32, (OutputId, LogicalPosition)>,
     active_touch: Option<i32>,
-
-    width: u32,
-    height: u32,
+    keyboard_focus: Option<OutputId>,
+    modifiers: Modifiers,
+    // depressed, latched and locked modifiers as sent by the compositor, SCTK's `Modifiers` lack e.g. AltGr
+    modifier_masks: [u32; 3],
+    keymap: Option<xkb::Keymap>,
+    // names of the layouts in the keymap and the index of the one the compositor uses
+    layouts: Vec<String>,
+    active_layout: u32,
+    // chosen with the `switch_layout` callback, keys are translated with it instead of the active layout
+    selected_layout: Option<u32>,
 
     registry_state: RegistryState,
-    wl_surface: wl_surface::WlSurface,
     wl_display: wl_display::WlDisplay,
+    compositor: wl_compositor::WlCompositor,
+    session_lock_manager: ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+    // only used by the UI thread when rendering in software, but buffer events are dispatched here
+    shm: Shm,
+    viewporter: Option<wp_viewporter::WpViewporter>,
+    fractional_scale_manager: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
     session_lock: ext_session_lock_v1::ExtSessionLockV1,
+    outputs: HashMap<OutputId, LockedOutput>,
+    // surfaces of removed outputs which the UI thread may still render to
+    retired_surfaces: HashMap<OutputId, wl_surface::WlSurface>,
 
     seat_state: SeatState,
     keyboard: Option<wl_keyboard::WlKeyboard>,
@@ -129,56 +408,350 @@ struct AppData {
     touch: Option<wl_touch::WlTouch>,
 
     render_thread_sender: Sender<WindowingMessage>,
+    hooks: Hooks,
+    grace: GracePeriod,
+}
+
+/// Input shortly after the session was locked unlocks it again without a password
+struct GracePeriod {
+    duration: Duration,
+    // input before this unlocks the session, set once it is locked
+    until: Option<Instant>,
+    /// Input arrived in time
+    unlock: bool,
+}
+
+impl GracePeriod {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            until: None,
+            unlock: false,
+        }
+    }
+
+    /// Start the grace period once the session is locked. Returns its length, `None` without one
+    fn start(&mut self, now: Instant) -> Option<Duration> {
+        if self.duration.is_zero() {
+            return None;
+        }
+        self.until = Some(now + self.duration);
+        Some(self.duration)
+    }
+
+    /// Only deliberate input counts. Releasing the keys of the shortcut that locked the session must not
+    /// unlock it again, and the pointer leaves surfaces without the user doing anything, e.g. when an output is removed
+    fn input(&mut self, event: &WindowEvent, now: Instant) {
+        let deliberate = matches!(
+            event,
+            WindowEvent::KeyPressed { .. }
+                | WindowEvent::PointerPressed { .. }
+                | WindowEvent::PointerMoved { .. }
+                | WindowEvent::PointerScrolled { .. }
+        );
+        if deliberate && self.until.is_some_and(|until| now < until) {
+            self.unlock = true;
+        }
+    }
+
+    /// Returns whether it was still running
+    fn end(&mut self) -> bool {
+        self.until.take().is_some()
+    }
 }
 
 impl AppData {
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        registry_state: RegistryState,
+        globals: &GlobalList,
+        qh: &QueueHandle<Self>,
         display: wl_display::WlDisplay,
-        surface: wl_surface::WlSurface,
-        session_lock: ext_session_lock_v1::ExtSessionLockV1,
-        seat_state: SeatState,
+        compositor: wl_compositor::WlCompositor,
+        shm: Shm,
+        session_lock_manager: ext_session_lock_manager_v1::ExtSessionLockManagerV1,
         sender: Sender<WindowingMessage>,
+        hooks: Hooks,
+        grace_period: Duration,
     ) -> Self {
+        let viewporter: Option<wp_viewporter::WpViewporter> = globals.bind(qh, 1..=1, ()).ok();
+        // fractional scales can only be applied using a viewport
+        let fractional_scale_manager = viewporter
+            .as_ref()
+            .and_then(|_| globals.bind(qh, 1..=1, ()).ok());
+        let session_lock = session_lock_manager.lock(qh, ());
+
         Self {
             running: true,
             locked: false,
-            configured: false,
-            registry_state,
-            wl_surface: surface,
-            width: 0,
-            height: 0,
-            session_lock,
+            lock_finished: false,
+            registry_state: RegistryState::new(globals),
             wl_display: display,
-            seat_state,
+            compositor,
+            session_lock_manager,
+            shm,
+            viewporter,
+            fractional_scale_manager,
+            session_lock,
+            outputs: HashMap::new(),
+            retired_surfaces: HashMap::new(),
+            seat_state: SeatState::new(globals, qh),
             keyboard: None,
             pointer: None,
             touch: None,
             render_thread_sender: sender,
+            hooks,
+            grace: GracePeriod::new(grace_period),
             touches: HashMap::new(),
             active_touch: None,
+            keyboard_focus: None,
+            modifiers: Modifiers::default(),
+            modifier_masks: [0; 3],
+            keymap: None,
+            layouts: Vec::new(),
+            active_layout: 0,
+            selected_layout: None,
+        }
+    }
+
+    /// Bind the `wl_output` global `id` and create a lock surface covering it. The surface is announced to the UI thread once it is configured
+    fn add_output(&mut self, qh: &QueueHandle<Self>, id: OutputId) {
+        let output: wl_output::WlOutput = match self.registry_state.bind_specific(qh, id, 1..=4, id)
+        {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!("failed to bind output {id}: {e}");
+                return;
+            }
+        };
+        log::debug!("locking output {id}");
+
+        let wl_surface = self.compositor.create_surface(qh, id);
+        // set surface role as session lock surface
+        let lock_surface = self
+            .session_lock
+            .get_lock_surface(&wl_surface, &output, qh, id);
+        let viewport = self
+            .viewporter
+            .as_ref()
+            .map(|viewporter| viewporter.get_viewport(&wl_surface, qh, ()));
+        let fractional_scale = self
+            .fractional_scale_manager
+            .as_ref()
+            .map(|manager| manager.get_fractional_scale(&wl_surface, qh, id));
+
+        self.outputs.insert(
+            id,
+            LockedOutput {
+                output,
+                wl_surface,
+                lock_surface,
+                viewport,
+                fractional_scale,
+                info: OutputInfo::default(),
+                configured: false,
+                presented: false,
+                preferred_fractional_scale: None,
+                preferred_buffer_scale: None,
+                output_scale: 1,
+                sent_scale: 1.,
+            },
+        );
+    }
+
+    /// Ask the compositor for a new lock after it refused the last one. The lock surfaces are recreated like after a hotplug
+    fn relock(&mut self, qh: &QueueHandle<Self>) {
+        let outputs: Vec<OutputId> = self.outputs.keys().copied().collect();
+        for id in &outputs {
+            self.remove_output(*id);
+        }
+        self.session_lock.destroy();
+
+        self.session_lock = self.session_lock_manager.lock(qh, ());
+        self.lock_finished = false;
+        for id in outputs {
+            self.add_output(qh, id);
+        }
+    }
+
+    /// Tear down the lock surface of an output that has been unplugged
+    fn remove_output(&mut self, id: OutputId) {
+        let Some(output) = self.outputs.remove(&id) else {
+            return;
+        };
+        log::debug!("output {id} removed");
+
+        output.lock_surface.destroy();
+        if let Some(viewport) = output.viewport {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = output.fractional_scale {
+            fractional_scale.destroy();
+        }
+        if output.output.version() >= 3 {
+            output.output.release();
+        }
+
+        if self.keyboard_focus == Some(id) {
+            self.keyboard_focus = None;
+        }
+        self.touches
+            .retain(|_, (touch_output, _)| *touch_output != id);
+        if self
+            .active_touch
+            .is_some_and(|touch| !self.touches.contains_key(&touch))
+        {
+            self.active_touch = None;
+        }
+
+        // The surface is destroyed once the UI thread has dropped its render context
+        self.retired_surfaces.insert(id, output.wl_surface);
+        self.render_thread_sender
+            .send(WindowingMessage::OutputRemoved { output: id })
+            .unwrap();
+    }
+
+    /// Pass a changed scale factor on to the UI thread. Before the surface is configured it is sent with `SurfaceReady`
+    fn update_scale(&mut self, id: OutputId) {
+        let Some(output) = self.outputs.get_mut(&id) else {
+            return;
+        };
+        let scale = output.scale_factor();
+        if !output.configured || scale == output.sent_scale {
+            return;
         }
+        log::debug!("output {id} changed scale to {scale}");
+
+        output.sent_scale = scale;
+        self.render_thread_sender
+            .send(WindowingMessage::ScaleChanged { output: id, scale })
+            .unwrap();
+    }
+
+    /// Find the output a surface belongs to
+    fn output_for_surface(&self, surface: &wl_surface::WlSurface) -> Option<OutputId> {
+        self.outputs
+            .iter()
+            .find(|(_, output)| &output.wl_surface == surface)
+            .map(|(id, _)| *id)
+    }
+
+    /// The output keyboard input is sent to. Falls back to any output if the compositor has not focused one yet
+    fn keyboard_output(&self) -> Option<OutputId> {
+        self.keyboard_focus
+            .or_else(|| self.outputs.keys().min().copied())
+    }
+
+    fn send_window_event(&mut self, output: OutputId, event: WindowEvent) {
+        self.grace.input(&event, Instant::now());
+        self.render_thread_sender
+            .send(WindowingMessage::SlintWindowEvent { output, event })
+            .unwrap();
+    }
+
+    fn end_grace_period(&mut self) {
+        if self.grace.end() {
+            let _ = self
+                .render_thread_sender
+                .send(WindowingMessage::GracePeriod {
+                    remaining: Duration::ZERO,
+                });
+        }
+    }
+
+    fn send_keyboard_state(&self) {
+        let layout = self
+            .layouts
+            .get(self.selected_layout.unwrap_or(self.active_layout) as usize)
+            .cloned()
+            .unwrap_or_default();
+        let _ = self
+            .render_thread_sender
+            .send(WindowingMessage::KeyboardState {
+                caps_lock: self.modifiers.caps_lock,
+                num_lock: self.modifiers.num_lock,
+                layout,
+            });
     }
 }
 
 // Ignore events from these object types
 delegate_noop!(AppData: ignore wl_compositor::WlCompositor);
-delegate_noop!(AppData: ignore wl_surface::WlSurface);
+delegate_noop!(AppData: ignore wp_viewporter::WpViewporter);
+delegate_noop!(AppData: ignore wp_viewport::WpViewport);
+delegate_noop!(AppData: ignore wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1);
 delegate_noop!(AppData: ignore wl_buffer::WlBuffer);
-delegate_noop!(AppData: ignore wl_output::WlOutput);
+delegate_noop!(AppData: ignore wl_callback::WlCallback);
 delegate_noop!(AppData: ignore ext_session_lock_manager_v1::ExtSessionLockManagerV1);
 // Delegate input
 delegate_seat!(AppData);
-delegate_keyboard!(AppData);
 delegate_pointer!(AppData);
 delegate_touch!(AppData);
 delegate_registry!(AppData);
+delegate_shm!(AppData);
+
+impl ShmHandler for AppData {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
 
 impl ProvidesRegistryState for AppData {
     fn registry(&mut self) -> &mut RegistryState {
         &mut self.registry_state
     }
-    registry_handlers![SeatState,];
+    registry_handlers![SeatState, LockedOutput,];
+}
+
+impl RegistryHandler<AppData> for LockedOutput {
+    fn new_global(
+        state: &mut AppData,
+        _: &Connection,
+        qh: &QueueHandle<AppData>,
+        name: u32,
+        interface: &str,
+        _: u32,
+    ) {
+        if interface == wl_output::WlOutput::interface().name {
+            state.add_output(qh, name);
+        }
+    }
+
+    fn remove_global(
+        state: &mut AppData,
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+        name: u32,
+        interface: &str,
+    ) {
+        if interface == wl_output::WlOutput::interface().name {
+            state.remove_output(name);
+        }
+    }
+}
+
+// SCTK only reports some of the modifiers, the raw masks are needed to translate keys with another layout
+impl Dispatch<wl_keyboard::WlKeyboard, KeyboardData<AppData>> for AppData {
+    fn event(
+        state: &mut Self,
+        keyboard: &wl_keyboard::WlKeyboard,
+        event: wl_keyboard::Event,
+        data: &KeyboardData<AppData>,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_keyboard::Event::Modifiers {
+            mods_depressed,
+            mods_latched,
+            mods_locked,
+            ..
+        } = event
+        {
+            state.modifier_masks = [mods_depressed, mods_latched, mods_locked];
+        }
+        <SeatState as Dispatch<wl_keyboard::WlKeyboard, KeyboardData<AppData>, AppData>>::event(
+            state, keyboard, event, data, conn, qh,
+        );
+    }
 }
 
 impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for AppData {
@@ -193,23 +766,121 @@ impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for AppData {
         match event {
             ext_session_lock_v1::Event::Locked => {
                 state.locked = true;
+                if let Some(remaining) = state.grace.start(Instant::now()) {
+                    let _ = state
+                        .render_thread_sender
+                        .send(WindowingMessage::GracePeriod { remaining });
+                }
+                state.hooks.run(
+                    HookEvent::Lock,
+                    &[("CTHULOCK_OUTPUTS", state.outputs.len().to_string())],
+                );
             }
             ext_session_lock_v1::Event::Finished => {
-                state.running = false;
+                state.lock_finished = true;
             }
             _ => {}
         };
     }
 }
 
-impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, ()> for AppData {
+impl Dispatch<wl_output::WlOutput, OutputId> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wl_output::WlOutput,
+        event: wl_output::Event,
+        output_id: &OutputId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.outputs.get_mut(output_id) else {
+            return;
+        };
+        match event {
+            wl_output::Event::Geometry { make, model, .. } => {
+                output.info.make = Some(make);
+                output.info.model = Some(model);
+            }
+            wl_output::Event::Name { name } => {
+                output.info.name = Some(name);
+            }
+            wl_output::Event::Description { description } => {
+                output.info.description = Some(description);
+            }
+            wl_output::Event::Scale { factor } => {
+                output.output_scale = factor;
+                state.update_scale(*output_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_surface::WlSurface, OutputId> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wl_surface::WlSurface,
+        event: wl_surface::Event,
+        output_id: &OutputId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_surface::Event::PreferredBufferScale { factor } = event {
+            if let Some(output) = state.outputs.get_mut(output_id) {
+                output.preferred_buffer_scale = Some(factor);
+                state.update_scale(*output_id);
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, OutputId> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        output_id: &OutputId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            if let Some(output) = state.outputs.get_mut(output_id) {
+                output.presented = true;
+            }
+            state
+                .render_thread_sender
+                .send(WindowingMessage::FrameDone { output: *output_id })
+                .unwrap();
+        }
+    }
+}
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, OutputId> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        output_id: &OutputId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            if let Some(output) = state.outputs.get_mut(output_id) {
+                output.preferred_fractional_scale = Some(scale);
+                state.update_scale(*output_id);
+            }
+        }
+    }
+}
+
+impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, OutputId> for AppData {
     fn event(
         state: &mut Self,
         surface: &ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
         event: ext_session_lock_surface_v1::Event,
-        _: &(),
+        output_id: &OutputId,
         _: &Connection,
-        _: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
     ) {
         if let ext_session_lock_surface_v1::Event::Configure {
             serial,
@@ -217,22 +888,45 @@ impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, ()> for AppD
             height,
         } = event
         {
-            log::debug!("surface reconfigure serial: {serial}");
+            log::debug!("surface reconfigure on output {output_id} serial: {serial}");
 
-            state.width = width;
-            state.height = height;
+            let display_id = state.wl_display.id();
+            let Some(output) = state.outputs.get_mut(output_id) else {
+                return;
+            };
 
-            let sender = &state.render_thread_sender;
-            if !state.configured {
-                sender
+            // the serial is acked by the UI thread, until it rendered at the new size its frames still have the old one
+            if !output.configured {
+                output.sent_scale = output.scale_factor();
+                state
+                    .render_thread_sender
                     .send(WindowingMessage::SurfaceReady {
-                        display_id: state.wl_display.id(),
-                        surface_id: state.wl_surface.id(),
+                        output: *output_id,
+                        info: Box::new(output.info.clone()),
+                        display_id,
+                        surface: Box::new(LockSurface {
+                            surface: output.wl_surface.clone(),
+                            lock_surface: surface.clone(),
+                            viewport: output.viewport.clone(),
+                            qh: qh.clone(),
+                            output: *output_id,
+                        }),
+                        shm: state.shm.wl_shm().clone(),
                         size: (width, height),
+                        scale: output.sent_scale,
+                        serial,
+                    })
+                    .unwrap();
+                output.configured = true;
+            } else {
+                state
+                    .render_thread_sender
+                    .send(WindowingMessage::Resized {
+                        output: *output_id,
+                        size: (width, height),
+                        serial,
                     })
                     .unwrap();
-                state.configured = true;
-                surface.ack_configure(serial);
             }
         }
     }
@@ -316,11 +1010,12 @@ impl KeyboardHandler for AppData {
         _: &Connection,
         _: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
-        _: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _: u32,
         _: &[u32],
         _keysyms: &[Keysym],
     ) {
+        self.keyboard_focus = self.output_for_surface(surface);
     }
 
     fn leave(
@@ -328,9 +1023,12 @@ impl KeyboardHandler for AppData {
         _: &Connection,
         _: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
-        _: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _: u32,
     ) {
+        if self.keyboard_focus == self.output_for_surface(surface) {
+            self.keyboard_focus = None;
+        }
     }
 
     fn press_key(
@@ -341,13 +1039,18 @@ impl KeyboardHandler for AppData {
         _: u32,
         event: KeyEvent,
     ) {
-        if let Some(text) = sctk_key_event_to_slint(event) {
-            self.render_thread_sender
-                .send(WindowingMessage::SlintWindowEvent(
-                    WindowEvent::KeyPressed { text },
-                ))
-                .unwrap();
-        }
+        let layout = selected_layout_state(
+            self.keymap.as_ref(),
+            self.modifier_masks,
+            self.selected_layout,
+        );
+        let (Some(output), Some(text)) = (
+            self.keyboard_output(),
+            sctk_key_event_to_slint(event, layout.as_ref()),
+        ) else {
+            return;
+        };
+        self.send_window_event(output, WindowEvent::KeyPressed { text });
     }
 
     fn release_key(
@@ -358,13 +1061,18 @@ impl KeyboardHandler for AppData {
         _: u32,
         event: KeyEvent,
     ) {
-        if let Some(text) = sctk_key_event_to_slint(event) {
-            self.render_thread_sender
-                .send(WindowingMessage::SlintWindowEvent(
-                    WindowEvent::KeyReleased { text },
-                ))
-                .unwrap();
-        }
+        let layout = selected_layout_state(
+            self.keymap.as_ref(),
+            self.modifier_masks,
+            self.selected_layout,
+        );
+        let (Some(output), Some(text)) = (
+            self.keyboard_output(),
+            sctk_key_event_to_slint(event, layout.as_ref()),
+        ) else {
+            return;
+        };
+        self.send_window_event(output, WindowEvent::KeyReleased { text });
     }
 
     fn update_modifiers(
@@ -373,13 +1081,86 @@ impl KeyboardHandler for AppData {
         _: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
         _serial: u32,
-        _: Modifiers,
-        _layout: u32,
+        modifiers: Modifiers,
+        layout: u32,
     ) {
+        let changed = modifiers.caps_lock != self.modifiers.caps_lock
+            || modifiers.num_lock != self.modifiers.num_lock
+            || layout != self.active_layout;
+        if layout != self.active_layout {
+            // switching layouts with the compositor overrides the one selected on the lock screen
+            self.selected_layout = None;
+        }
+        self.modifiers = modifiers;
+        self.active_layout = layout;
+        if changed {
+            self.send_keyboard_state();
+        }
+    }
+
+    fn update_keymap(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        keymap: Keymap<'_>,
+    ) {
+        // SCTK's keymap can't be shared, so it is compiled again
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        self.keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap.as_string(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        self.layouts = match &self.keymap {
+            Some(keymap) => (0..keymap.num_layouts())
+                .map(|layout| keymap.layout_get_name(layout).to_owned())
+                .collect(),
+            None => {
+                log::warn!("failed to compile the keymap");
+                Vec::new()
+            }
+        };
+        log::debug!("keyboard layouts: {:?}", self.layouts);
+        self.selected_layout = None;
+
+        let _ = self
+            .render_thread_sender
+            .send(WindowingMessage::KeyboardLayouts {
+                layouts: self.layouts.clone(),
+            });
+        self.send_keyboard_state();
     }
 }
 
-fn sctk_key_event_to_slint(event: KeyEvent) -> Option<SharedString> {
+/// State translating keys with the layout selected on the lock screen, if it differs from the compositor's.
+/// Only the layout is overridden, the modifiers are the compositor's depressed, latched and locked masks
+fn selected_layout_state(
+    keymap: Option<&xkb::Keymap>,
+    [depressed, latched, locked]: [u32; 3],
+    layout: Option<u32>,
+) -> Option<xkb::State> {
+    let (keymap, layout) = (keymap?, layout?);
+    let mut state = xkb::State::new(keymap);
+    state.update_mask(depressed, latched, locked, 0, 0, layout);
+    Some(state)
+}
+
+fn sctk_key_event_to_slint(
+    mut event: KeyEvent,
+    layout: Option<&xkb::State>,
+) -> Option<SharedString> {
+    if let Some(state) = layout {
+        // evdev scancodes are offset by 8 in xkb
+        let keycode = xkb::Keycode::new(event.raw_code + 8);
+        event.keysym = state.key_get_one_sym(keycode);
+        // release events carry no text
+        if event.utf8.is_some() {
+            event.utf8 = Some(state.key_get_utf8(keycode)).filter(|utf8| !utf8.is_empty());
+        }
+    }
+
     match event.keysym {
         Keysym::BackSpace => Some(Key::Backspace.into()),
         Keysym::Tab => Some(Key::Tab.into()),
@@ -410,64 +1191,63 @@ impl PointerHandler for AppData {
     ) {
         use PointerEventKind::*;
         for event in events {
-            let position = LogicalPosition::new(event.position.0 as f32, event.position.1 as f32);
+            let Some(output) = self.output_for_surface(&event.surface) else {
+                continue;
+            };
+            let position = surface_to_slint_position(event.position);
 
             match event.kind {
                 Enter { .. } => {}
                 Leave { .. } => {
-                    self.render_thread_sender
-                        .send(WindowingMessage::SlintWindowEvent(
-                            WindowEvent::PointerExited,
-                        ))
-                        .unwrap();
+                    self.send_window_event(output, WindowEvent::PointerExited);
                 }
                 Motion { .. } => {
-                    self.render_thread_sender
-                        .send(WindowingMessage::SlintWindowEvent(
-                            WindowEvent::PointerMoved { position },
-                        ))
-                        .unwrap();
+                    self.send_window_event(output, WindowEvent::PointerMoved { position });
                 }
                 Press { button, .. } => {
-                    self.render_thread_sender
-                        .send(WindowingMessage::SlintWindowEvent(
-                            WindowEvent::PointerPressed {
-                                position,
-                                button: wl_pointer_button_to_slint(button),
-                            },
-                        ))
-                        .unwrap();
+                    self.send_window_event(
+                        output,
+                        WindowEvent::PointerPressed {
+                            position,
+                            button: wl_pointer_button_to_slint(button),
+                        },
+                    );
                 }
                 Release { button, .. } => {
-                    self.render_thread_sender
-                        .send(WindowingMessage::SlintWindowEvent(
-                            WindowEvent::PointerReleased {
-                                position,
-                                button: wl_pointer_button_to_slint(button),
-                            },
-                        ))
-                        .unwrap();
+                    self.send_window_event(
+                        output,
+                        WindowEvent::PointerReleased {
+                            position,
+                            button: wl_pointer_button_to_slint(button),
+                        },
+                    );
                 }
                 Axis {
                     horizontal,
                     vertical,
                     ..
                 } => {
-                    self.render_thread_sender
-                        .send(WindowingMessage::SlintWindowEvent(
-                            WindowEvent::PointerScrolled {
-                                position,
-                                delta_x: horizontal.absolute as f32,
-                                delta_y: vertical.absolute as f32,
-                            },
-                        ))
-                        .unwrap();
+                    self.send_window_event(
+                        output,
+                        WindowEvent::PointerScrolled {
+                            position,
+                            delta_x: horizontal.absolute as f32,
+                            delta_y: vertical.absolute as f32,
+                        },
+                    );
                 }
             }
         }
     }
 }
 
+/// Convert surface-local coordinates of pointer and touch events into Slint coordinates.
+/// Slint renders with the scale factor of the output, so its logical pixels are the surface-local
+/// coordinates and the buffer scale or viewport doesn't have to be applied here
+fn surface_to_slint_position(position: (f64, f64)) -> LogicalPosition {
+    LogicalPosition::new(position.0 as f32, position.1 as f32)
+}
+
 fn wl_pointer_button_to_slint(button: u32) -> PointerEventButton {
     match button {
         272 => PointerEventButton::Left,
@@ -487,16 +1267,15 @@ impl TouchHandler for AppData {
         _time: u32,
         id: i32,
     ) {
-        if let Some(position) = self.touches.remove(&id) {
+        if let Some((output, position)) = self.touches.remove(&id) {
             if self.active_touch == Some(id) {
-                self.render_thread_sender
-                    .send(WindowingMessage::SlintWindowEvent(
-                        WindowEvent::PointerReleased {
-                            position,
-                            button: PointerEventButton::Left,
-                        },
-                    ))
-                    .unwrap();
+                self.send_window_event(
+                    output,
+                    WindowEvent::PointerReleased {
+                        position,
+                        button: PointerEventButton::Left,
+                    },
+                );
                 self.active_touch = None;
             }
         }
@@ -508,22 +1287,24 @@ impl TouchHandler for AppData {
         _touch: &wl_touch::WlTouch,
         _serial: u32,
         _time: u32,
-        _surface: wl_surface::WlSurface,
+        surface: wl_surface::WlSurface,
         id: i32,
         position: (f64, f64),
     ) {
-        let position = LogicalPosition::new(position.0 as f32, position.1 as f32);
-        self.touches.insert(id, position);
+        let Some(output) = self.output_for_surface(&surface) else {
+            return;
+        };
+        let position = surface_to_slint_position(position);
+        self.touches.insert(id, (output, position));
         if self.active_touch.is_none() {
             self.active_touch = Some(id);
-            self.render_thread_sender
-                .send(WindowingMessage::SlintWindowEvent(
-                    WindowEvent::PointerPressed {
-                        position,
-                        button: PointerEventButton::Left,
-                    },
-                ))
-                .unwrap();
+            self.send_window_event(
+                output,
+                WindowEvent::PointerPressed {
+                    position,
+                    button: PointerEventButton::Left,
+                },
+            );
         }
     }
     fn motion(
@@ -535,14 +1316,13 @@ impl TouchHandler for AppData {
         id: i32,
         position: (f64, f64),
     ) {
-        let position = LogicalPosition::new(position.0 as f32, position.1 as f32);
-        self.touches.insert(id, position);
+        let Some((output, _)) = self.touches.get(&id).copied() else {
+            return;
+        };
+        let position = surface_to_slint_position(position);
+        self.touches.insert(id, (output, position));
         if self.active_touch == Some(id) {
-            self.render_thread_sender
-                .send(WindowingMessage::SlintWindowEvent(
-                    WindowEvent::PointerMoved { position },
-                ))
-                .unwrap();
+            self.send_window_event(output, WindowEvent::PointerMoved { position });
         }
     }
     fn shape(
@@ -556,18 +1336,19 @@ impl TouchHandler for AppData {
     ) {
     }
     fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _touch: &wl_touch::WlTouch) {
-        for (id, position) in self.touches.drain() {
+        let touches: Vec<_> = self.touches.drain().collect();
+        for (id, (output, position)) in touches {
             if self.active_touch == Some(id) {
-                self.render_thread_sender
-                    .send(WindowingMessage::SlintWindowEvent(
-                        WindowEvent::PointerReleased {
-                            position,
-                            button: PointerEventButton::Left,
-                        },
-                    ))
-                    .unwrap();
+                self.send_window_event(
+                    output,
+                    WindowEvent::PointerReleased {
+                        position,
+                        button: PointerEventButton::Left,
+                    },
+                );
             }
         }
+        self.active_touch = None;
     }
     fn orientation(
         &mut self,
@@ -579,3 +1360,82 @@ impl TouchHandler for AppData {
     ) {
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use slint::{
+        platform::{Key, PointerEventButton, WindowEvent},
+        LogicalPosition,
+    };
+    use xkbcommon::xkb;
+
+    use super::{selected_layout_state, GracePeriod};
+
+    #[test]
+    fn test_grace_period() {
+        let locked = Instant::now();
+        let position = LogicalPosition::new(0., 0.);
+        let release = WindowEvent::KeyReleased {
+            text: Key::Control.into(),
+        };
+        let press = WindowEvent::PointerPressed {
+            position,
+            button: PointerEventButton::Left,
+        };
+
+        let mut grace = GracePeriod::new(Duration::ZERO);
+        assert_eq!(grace.start(locked), None);
+        grace.input(&press, locked);
+        assert!(!grace.unlock);
+
+        let mut grace = GracePeriod::new(Duration::from_secs(5));
+        // input before the session is locked doesn't count
+        grace.input(&press, locked);
+        assert!(!grace.unlock);
+        assert_eq!(grace.start(locked), Some(Duration::from_secs(5)));
+        // releasing the keys of the lock shortcut
+        grace.input(&release, locked + Duration::from_secs(1));
+        grace.input(&WindowEvent::PointerExited, locked + Duration::from_secs(1));
+        assert!(!grace.unlock);
+        grace.input(&press, locked + Duration::from_secs(6));
+        assert!(!grace.unlock);
+        grace.input(&press, locked + Duration::from_secs(1));
+        assert!(grace.unlock);
+
+        let mut grace = GracePeriod::new(Duration::from_secs(5));
+        grace.start(locked);
+        assert!(grace.end());
+        assert!(!grace.end());
+        grace.input(&press, locked + Duration::from_secs(1));
+        assert!(!grace.unlock);
+    }
+
+    #[test]
+    fn test_selected_layout_level3() {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            "us,de",
+            "",
+            None,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("compiling the keymap needs xkeyboard-config");
+        // AltGr held on the German layout, as reported by wl_keyboard.modifiers
+        let mut compositor = xkb::State::new(&keymap);
+        compositor.update_mask(0, 0, 0, 0, 0, 1);
+        compositor.update_key(xkb::Keycode::new(108), xkb::KeyDirection::Down);
+        let masks = [
+            compositor.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+            compositor.serialize_mods(xkb::STATE_MODS_LATCHED),
+            compositor.serialize_mods(xkb::STATE_MODS_LOCKED),
+        ];
+
+        let state = selected_layout_state(Some(&keymap), masks, Some(1)).unwrap();
+        assert_eq!(state.key_get_utf8(xkb::Keycode::new(24)), "@");
+    }
+}